@@ -0,0 +1,222 @@
+use flate2::read::GzDecoder;
+use std::fs;
+use std::io::{self, Cursor, Read};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const SEVENZ_MAGIC: &[u8] = &[b'7', b'z', 0xbc, 0xaf, 0x27, 0x1c];
+
+// Reads a rom from a plain file or from inside a zip, 7z or gzip archive. A specific entry can
+// be picked with `rom.zip#game.gbc`, otherwise the first gb or gbc entry is used. The returned
+// path is the one of the rom itself, as if it sat next to the archive, so that saves and patches
+// are named after the game rather than the archive.
+pub fn read(path: &Path) -> Result<(PathBuf, Vec<u8>), Error> {
+    let (path, entry) = split_entry(path);
+    let data = fs::read(&path)?;
+
+    let (name, data) = if data.starts_with(ZIP_MAGIC) {
+        read_zip(&data, entry.as_deref())?
+    } else if data.starts_with(SEVENZ_MAGIC) {
+        read_7z(&data, entry.as_deref())?
+    } else if data.starts_with(GZIP_MAGIC) {
+        let mut rom = Vec::new();
+        GzDecoder::new(&data[..]).read_to_end(&mut rom)?;
+        (
+            path.file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into(),
+            rom,
+        )
+    } else {
+        return Ok((path, data));
+    };
+    Ok((path.with_file_name(file_name(&name)), data))
+}
+
+// Only splits off the entry name when the path as a whole does not exist, as `#` is a valid
+// character in file names.
+fn split_entry(path: &Path) -> (PathBuf, Option<String>) {
+    if path.exists() {
+        return (path.to_path_buf(), None);
+    }
+    let s = path.to_string_lossy();
+    match s.rfind('#') {
+        Some(i) => (PathBuf::from(&s[..i]), Some(s[i + 1..].to_string())),
+        None => (path.to_path_buf(), None),
+    }
+}
+
+fn read_zip(data: &[u8], entry: Option<&str>) -> Result<(String, Vec<u8>), Error> {
+    let mut zip = zip::ZipArchive::new(Cursor::new(data))?;
+    for i in 0..zip.len() {
+        let mut file = zip.by_index(i)?;
+        if file.is_dir() || !wanted(file.name(), entry) {
+            continue;
+        }
+        let name = file.name().to_string();
+        let mut rom = Vec::new();
+        file.read_to_end(&mut rom)?;
+        return Ok((name, rom));
+    }
+    Err(missing(entry))
+}
+
+fn read_7z(data: &[u8], entry: Option<&str>) -> Result<(String, Vec<u8>), Error> {
+    let mut archive = sevenz_rust::SevenZReader::new(
+        Cursor::new(data),
+        data.len() as u64,
+        sevenz_rust::Password::empty(),
+    )?;
+    let mut found = None;
+    archive.for_each_entries(|file, reader| {
+        // Returning false only ends the current folder, so later folders still call back.
+        if found.is_some() {
+            return Ok(false);
+        }
+        if file.is_directory() || !wanted(file.name(), entry) {
+            // Entries share a compressed stream, so skipped ones still have to be read through.
+            io::copy(reader, &mut io::sink())?;
+            return Ok(true);
+        }
+        let mut rom = Vec::new();
+        reader.read_to_end(&mut rom)?;
+        found = Some((file.name().to_string(), rom));
+        Ok(false)
+    })?;
+    found.ok_or_else(|| missing(entry))
+}
+
+fn wanted(name: &str, entry: Option<&str>) -> bool {
+    match entry {
+        Some(entry) => name == entry || file_name(name) == entry,
+        None => {
+            let name = name.to_lowercase();
+            name.ends_with(".gb") || name.ends_with(".gbc")
+        }
+    }
+}
+
+fn file_name(name: &str) -> &str {
+    name.rsplit(['/', '\\']).next().unwrap_or(name)
+}
+
+fn missing(entry: Option<&str>) -> Error {
+    match entry {
+        Some(entry) => Error::MissingEntry(entry.to_string()),
+        None => Error::NoRom,
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("no gb or gbc rom found in archive")]
+    NoRom,
+    #[error("{0} not found in archive")]
+    MissingEntry(String),
+    #[error("zip error: {source}")]
+    Zip {
+        #[from]
+        source: zip::result::ZipError,
+    },
+    #[error("7z error: {source}")]
+    SevenZ {
+        #[from]
+        source: sevenz_rust::Error,
+    },
+    #[error("io error: {source}")]
+    Io {
+        #[from]
+        source: io::Error,
+    },
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn picks_rom_from_zip() {
+        let dir = std::env::temp_dir().join(format!("geebee-archive-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("roms.zip");
+
+        let mut zip = zip::ZipWriter::new(fs::File::create(&path).unwrap());
+        let options = zip::write::FileOptions::default();
+        for (name, data) in &[("readme.txt", [0]), ("games/a.gb", [1]), ("b.gbc", [2])] {
+            zip.start_file(*name, options).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap();
+
+        assert_eq!(read(&path).unwrap(), (dir.join("a.gb"), vec![1]));
+        assert_eq!(
+            read(&dir.join("roms.zip#b.gbc")).unwrap(),
+            (dir.join("b.gbc"), vec![2])
+        );
+        assert!(matches!(
+            read(&dir.join("roms.zip#c.gb")),
+            Err(Error::MissingEntry(_))
+        ));
+
+        let mut zip = zip::ZipWriter::new(fs::File::create(&path).unwrap());
+        zip.start_file("readme.txt", options).unwrap();
+        zip.finish().unwrap();
+        assert!(matches!(read(&path), Err(Error::NoRom)));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn picks_rom_from_7z() {
+        let dir = std::env::temp_dir().join(format!("geebee-archive-7z-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("roms.7z");
+        let write = |entries: &[(&str, u8)]| {
+            let mut archive = sevenz_rust::SevenZWriter::create(&path).unwrap();
+            for &(name, data) in entries {
+                let mut entry = sevenz_rust::SevenZArchiveEntry::new();
+                entry.name = name.to_string();
+                archive
+                    .push_archive_entry(entry, Some(&[data][..]))
+                    .unwrap();
+            }
+            archive.finish().unwrap();
+        };
+
+        write(&[("readme.txt", 0), ("games/a.gb", 1), ("b.gbc", 2)]);
+        assert_eq!(read(&path).unwrap(), (dir.join("a.gb"), vec![1]));
+        assert_eq!(
+            read(&dir.join("roms.7z#b.gbc")).unwrap(),
+            (dir.join("b.gbc"), vec![2])
+        );
+
+        write(&[("readme.txt", 0)]);
+        assert!(matches!(read(&path), Err(Error::NoRom)));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reads_gzip() {
+        let dir = std::env::temp_dir().join(format!("geebee-archive-gz-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("game.gbc.gz");
+
+        let rom = (0..=255).collect::<Vec<u8>>();
+        let mut gzip = flate2::write::GzEncoder::new(
+            fs::File::create(&path).unwrap(),
+            flate2::Compression::default(),
+        );
+        gzip.write_all(&rom).unwrap();
+        gzip.finish().unwrap();
+
+        // A gzip file holds a single stream, which is named after the archive.
+        assert_eq!(read(&path).unwrap(), (dir.join("game.gbc"), rom));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,3 +1,8 @@
+use crate::archive;
+use crate::header::Header;
+use crate::patch;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
 use std::fs::File;
 use std::path::{Path, PathBuf};
 use std::string;
@@ -5,54 +10,119 @@ use std::{io, io::Read};
 use thiserror::Error;
 
 pub struct Cartridge {
-    title: String,
+    header: Header,
     cart_type: CartType,
-    ram_size: u8,
     gb: GBType,
-    sgb: bool,
     data: Vec<u8>,
     path: Option<PathBuf>,
+    save_dir: Option<PathBuf>,
+    patch: Option<PathBuf>,
+    lenient: bool,
+}
+
+impl Default for Cartridge {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Cartridge {
     pub fn new() -> Self {
         Self {
-            title: "EMPTY".to_string(),
+            header: Header {
+                title: "EMPTY".to_string(),
+                ram_size: 9,
+                ..Default::default()
+            },
             cart_type: CartType::default(),
-            ram_size: 9,
             gb: GBType::CGB(CGBType::SupportCGB),
-            sgb: false,
             data: vec![],
             path: None,
+            save_dir: None,
+            patch: None,
+            lenient: false,
         }
     }
 
-    pub fn with_path(mut self, cart: &Path) -> Result<Self, Error> {
-        let mut data = Vec::<u8>::new();
-        let mut file = File::open(cart)?;
-        file.read_to_end(&mut data)?;
+    // Lenient loading warns about broken headers instead of failing, so homebrew and hacked
+    // roms can still be run.
+    pub fn with_lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    // Keeps battery saves in the given directory instead of next to the rom.
+    pub fn with_save_dir(mut self, dir: &Path) -> Self {
+        self.save_dir = Some(PathBuf::from(dir));
+        self
+    }
+
+    // Applies an IPS, UPS or BPS patch when loading from a path. Without one, a patch with the
+    // same name as the rom is picked up automatically.
+    pub fn with_patch(mut self, patch: &Path) -> Self {
+        self.patch = Some(PathBuf::from(patch));
+        self
+    }
+
+    // Loads a rom file, which may also be inside an archive. The cartridge takes the path of the
+    // rom within it, so saves and patches are looked up by the name of the game.
+    pub fn with_path(mut self, path: &Path) -> Result<Self, Error> {
+        let (cart, mut data) = archive::read(path)?;
+
+        let patch = self.patch.clone().or_else(|| {
+            ["ips", "ups", "bps"]
+                .iter()
+                .map(|ext| cart.with_extension(ext))
+                .find(|p| p.exists())
+        });
+        if let Some(patch) = patch {
+            println!("Applying patch {}", patch.display());
+            let mut patch_data = Vec::<u8>::new();
+            File::open(&patch)?.read_to_end(&mut patch_data)?;
+            data = patch::apply(&patch_data, &data)?;
+            Self::fix_checksums(&mut data);
+        }
 
-        self.path = Some(PathBuf::from(cart));
+        self.path = Some(cart);
         self.with_data(&data)
     }
 
     pub fn with_data(mut self, data: &[u8]) -> Result<Self, Error> {
-        if data.len() < 16384 {
-            return Err(Error::InvalidRom);
+        let mut data = data.to_vec();
+        if data.len() < 0x4000 {
+            self.check(Error::InvalidRom)?;
         }
-        Self::verify_checksum(&data)?;
-        self.title = String::from_utf8((data[0x0134..0x134 + 11]).to_vec())?
-            .trim_matches(char::from(0))
-            .to_string();
-        self.cart_type = CartType::from(data[0x0147]);
-        self.ram_size = data[0x149];
-        self.gb = match data[0x0143] {
+        if data.len() < 0x8000 && self.lenient {
+            data.resize(0x8000, 0xff);
+        }
+        let header = match Self::mmm01_header(&data) {
+            Some(header) => header,
+            None => Header::parse(&data)?,
+        };
+        if !header.header_checksum_valid {
+            self.check(Error::ChecksumFailed)?;
+        }
+        if let Err(e) = Self::verify_rom_size(&header, &data) {
+            self.check(e)?;
+            let size = header.rom_banks.unwrap_or(0) * 0x4000;
+            if data.len() < size {
+                data.resize(size, 0xff);
+            }
+        }
+        self.cart_type = match CartType::try_from(header.cart_type) {
+            Ok(cart_type) => cart_type,
+            Err(e) => {
+                self.check(e)?;
+                CartType::default()
+            }
+        };
+        self.gb = match header.cgb_flag {
             0x80 => GBType::CGB(CGBType::SupportCGB),
             0xc0 => GBType::CGB(CGBType::OnlyCGB),
             _ => GBType::NonCGB,
         };
-        self.sgb = data[0x0146] == 0x03;
-        self.data = data.to_vec();
+        self.header = header;
+        self.data = data;
         println!(
             "Cart Data: {}, {:?} GB: {:?}",
             self.title(),
@@ -63,7 +133,7 @@ impl Cartridge {
     }
 
     pub fn title(&self) -> &String {
-        &self.title
+        &self.header.title
     }
 
     pub fn path(&self) -> Option<&Path> {
@@ -73,6 +143,10 @@ impl Cartridge {
         }
     }
 
+    pub fn save_dir(&self) -> Option<&Path> {
+        self.save_dir.as_deref()
+    }
+
     pub fn cart_type(&self) -> CartType {
         self.cart_type.clone()
     }
@@ -85,30 +159,66 @@ impl Cartridge {
         self.gb
     }
 
-    pub fn ram_size(&self) -> u8 {
-        self.ram_size
+    pub fn ram_size(&self) -> usize {
+        self.header.ram_bytes()
     }
 
-    fn verify_checksum(data: &[u8]) -> Result<(), Error> {
-        let mut x: u8 = 0;
-        for i in data.iter().take(0x14c + 1).skip(0x0134) {
-            x = x.overflowing_sub(*i).0.overflowing_sub(1).0;
+    // Patches rarely bother to update the header, so the checksums are recalculated to match
+    // the patched rom.
+    fn fix_checksums(data: &mut [u8]) {
+        if data.len() < 0x0150 {
+            return;
         }
-        if x != data[0x014d] {
-            Err(Error::ChecksumFailed)
+        data[0x014d] = Header::header_checksum(data);
+        let global = Header::global_checksum(data);
+        data[0x014e..0x0150].copy_from_slice(&global.to_be_bytes());
+    }
+
+    fn check(&self, error: Error) -> Result<(), Error> {
+        if self.lenient {
+            eprintln!("warning: {}", error);
+            Ok(())
+        } else {
+            Err(error)
+        }
+    }
+
+    // MMM01 multicarts boot into the menu in their last 32 KiB, which also holds the header
+    // describing the whole cartridge.
+    fn mmm01_header(data: &[u8]) -> Option<Header> {
+        if data.len() <= 0x8000 {
+            return None;
+        }
+        let header = Header::parse(&data[data.len() - 0x8000..]).ok()?;
+        match header.cart_type {
+            0x0b..=0x0d if header.header_checksum_valid => Some(header),
+            _ => None,
+        }
+    }
+
+    fn verify_rom_size(header: &Header, data: &[u8]) -> Result<(), Error> {
+        let expected = match header.rom_banks {
+            Some(banks) => banks * 0x4000,
+            None => return Err(Error::UnsupportedRomSize(header.rom_size)),
+        };
+        if data.len() < expected {
+            Err(Error::RomSizeMismatch {
+                expected,
+                actual: data.len(),
+            })
         } else {
             Ok(())
         }
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub enum GBType {
     NonCGB,
     CGB(CGBType),
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub enum CGBType {
     SupportCGB,
     OnlyCGB,
@@ -121,6 +231,7 @@ pub struct CartType {
     pub battery: bool,
     pub timer: bool,
     pub rumble: bool,
+    pub sensor: bool,
 }
 
 impl CartType {
@@ -146,6 +257,10 @@ impl CartType {
         self.rumble = true;
         self
     }
+    fn with_sensor(mut self) -> Self {
+        self.sensor = true;
+        self
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -153,9 +268,16 @@ pub enum Controller {
     None,
     MBC1,
     MBC2,
+    MMM01,
     MBC3,
     MBC4,
     MBC5,
+    MBC6,
+    MBC7,
+    HuC1,
+    HuC3,
+    Camera,
+    TAMA5,
 }
 
 impl Default for Controller {
@@ -164,9 +286,11 @@ impl Default for Controller {
     }
 }
 
-impl From<u8> for CartType {
-    fn from(t: u8) -> CartType {
-        match t {
+impl TryFrom<u8> for CartType {
+    type Error = Error;
+
+    fn try_from(t: u8) -> Result<CartType, Error> {
+        Ok(match t {
             0x00 => CartType::new(Controller::None),
             0x01 => CartType::new(Controller::MBC1),
             0x02 => CartType::new(Controller::MBC1).with_ram(),
@@ -175,6 +299,9 @@ impl From<u8> for CartType {
             0x06 => CartType::new(Controller::MBC2).with_battery(),
             0x08 => CartType::new(Controller::None).with_ram(),
             0x09 => CartType::new(Controller::None).with_ram().with_battery(),
+            0x0b => CartType::new(Controller::MMM01),
+            0x0c => CartType::new(Controller::MMM01).with_ram(),
+            0x0d => CartType::new(Controller::MMM01).with_ram().with_battery(),
             0x0f => CartType::new(Controller::MBC3).with_timer().with_battery(),
             0x10 => CartType::new(Controller::MBC3)
                 .with_timer()
@@ -195,8 +322,24 @@ impl From<u8> for CartType {
                 .with_rumble()
                 .with_ram()
                 .with_battery(),
-            _ => panic!("unable to handle cartridge type {}", t),
-        }
+            0x20 => CartType::new(Controller::MBC6).with_ram().with_battery(),
+            0x22 => CartType::new(Controller::MBC7)
+                .with_sensor()
+                .with_rumble()
+                .with_ram()
+                .with_battery(),
+            0xfc => CartType::new(Controller::Camera)
+                .with_sensor()
+                .with_ram()
+                .with_battery(),
+            0xfd => CartType::new(Controller::TAMA5).with_timer().with_battery(),
+            0xfe => CartType::new(Controller::HuC3)
+                .with_timer()
+                .with_ram()
+                .with_battery(),
+            0xff => CartType::new(Controller::HuC1).with_ram().with_battery(),
+            _ => return Err(Error::UnsupportedCartridge(t)),
+        })
     }
 }
 
@@ -206,7 +349,27 @@ pub enum Error {
     InvalidRom,
     #[error("checksum check fails")]
     ChecksumFailed,
-    #[error("io error")]
+    #[error("unsupported cartridge type {0:#04x}")]
+    UnsupportedCartridge(u8),
+    #[error("unsupported memory bank controller {0:?}")]
+    UnsupportedMapper(Controller),
+    #[error("unsupported rom size code {0:#04x}")]
+    UnsupportedRomSize(u8),
+    #[error("rom size mismatch: header expects {expected} bytes, found {actual}")]
+    RomSizeMismatch { expected: usize, actual: usize },
+    #[error("unable to read rom: {source}")]
+    Archive {
+        #[from]
+        source: archive::Error,
+    },
+    #[error("unable to apply patch: {source}")]
+    Patch {
+        #[from]
+        source: patch::Error,
+    },
+    #[error("unable to access save file: {source}")]
+    Save { source: io::Error },
+    #[error("io error: {source}")]
     Io {
         #[from]
         source: io::Error,
@@ -217,3 +380,34 @@ pub enum Error {
         source: string::FromUtf8Error,
     },
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn strict_rejects_broken_roms() {
+        assert!(matches!(
+            Cartridge::new().with_data(&[0; 0x100]),
+            Err(Error::InvalidRom)
+        ));
+        let mut data = vec![0; 0x8000];
+        data[0x014d] = 0x12;
+        assert!(matches!(
+            Cartridge::new().with_data(&data),
+            Err(Error::ChecksumFailed)
+        ));
+    }
+
+    #[test]
+    fn lenient_pads_and_falls_back() {
+        let mut data = vec![0; 0x150];
+        data[0x0147] = 0x04;
+        let cart = Cartridge::new()
+            .with_lenient(true)
+            .with_data(&data)
+            .unwrap();
+        assert_eq!(cart.data().len(), 0x8000);
+        assert!(matches!(cart.cart_type().controller, Controller::None));
+    }
+}
@@ -0,0 +1,219 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use thiserror::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Code {
+    // Replaces a rom byte as it is read, optionally only when it matches the compare value so
+    // that the patch only hits the intended bank.
+    GameGenie {
+        address: u16,
+        value: u8,
+        compare: Option<u8>,
+    },
+    // Writes a byte into cartridge or work ram every frame. The bank byte selects the cartridge
+    // ram bank for 0xa000-0xbfff, and 0x90-0x97 select the work ram bank for 0xd000-0xdfff.
+    GameShark {
+        bank: u8,
+        address: u16,
+        value: u8,
+    },
+}
+
+impl Code {
+    // Decodes `ABC-DEF` and `ABC-DEF-GHI` Game Genie codes or eight digit GameShark codes.
+    pub fn parse(code: &str) -> Result<Self, Error> {
+        let invalid = || Error::InvalidCode(code.to_string());
+        let digits = code
+            .chars()
+            .filter(|&c| c != '-')
+            .map(|c| c.to_digit(16).map(|d| d as u16))
+            .collect::<Option<Vec<u16>>>()
+            .ok_or_else(invalid)?;
+        let dashed = code.contains('-');
+        match digits.len() {
+            6 | 9 if dashed => {
+                let d = &digits;
+                let address = ((d[5] ^ 0x0f) << 12) | (d[2] << 8) | (d[3] << 4) | d[4];
+                if address >= 0x8000 {
+                    return Err(invalid());
+                }
+                // The compare value is stored rotated and scrambled, with the middle digit unused.
+                let compare = if d.len() == 9 {
+                    let c = ((d[6] << 4) | d[8]) as u8;
+                    Some(c.rotate_right(2) ^ 0xba)
+                } else {
+                    None
+                };
+                Ok(Code::GameGenie {
+                    address,
+                    value: ((d[0] << 4) | d[1]) as u8,
+                    compare,
+                })
+            }
+            8 if !dashed => {
+                let byte = |i: usize| ((digits[i] << 4) | digits[i + 1]) as u8;
+                let address = u16::from_le_bytes([byte(4), byte(6)]);
+                if !(0xa000..=0xdfff).contains(&address) {
+                    return Err(invalid());
+                }
+                Ok(Code::GameShark {
+                    bank: byte(0),
+                    value: byte(2),
+                    address,
+                })
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+pub struct Cheat {
+    pub name: String,
+    pub enabled: bool,
+    codes: Vec<Code>,
+}
+
+#[derive(Default)]
+pub struct Cheats {
+    cheats: Vec<Cheat>,
+}
+
+impl Cheats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Reads a cheat file with one cheat per line: the codes joined with `+`, followed by an
+    // optional name. Lines starting with `#` are comments and a leading `!` disables a cheat.
+    pub fn with_path(mut self, path: &Path) -> Result<Self, Error> {
+        for line in fs::read_to_string(path)?.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (enabled, line) = match line.strip_prefix('!') {
+                Some(line) => (false, line.trim_start()),
+                None => (true, line),
+            };
+            let mut parts = line.splitn(2, char::is_whitespace);
+            let codes = parts.next().unwrap_or_default();
+            let name = parts.next().map(str::trim).unwrap_or(codes);
+            self.add(name, codes)?;
+            if let Some(cheat) = self.cheats.last_mut() {
+                cheat.enabled = enabled;
+            }
+        }
+        Ok(self)
+    }
+
+    pub fn add(&mut self, name: &str, codes: &str) -> Result<(), Error> {
+        let codes = codes
+            .split('+')
+            .map(Code::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        self.cheats.push(Cheat {
+            name: name.to_string(),
+            enabled: true,
+            codes,
+        });
+        Ok(())
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Cheat> {
+        self.cheats.get(index)
+    }
+
+    // Flips a cheat on or off, returning its new state.
+    pub fn toggle(&mut self, index: usize) -> Option<bool> {
+        let cheat = self.cheats.get_mut(index)?;
+        cheat.enabled = !cheat.enabled;
+        Some(cheat.enabled)
+    }
+
+    fn codes(&self) -> impl Iterator<Item = &Code> {
+        self.cheats
+            .iter()
+            .filter(|c| c.enabled)
+            .flat_map(|c| c.codes.iter())
+    }
+
+    // Applies the Game Genie patches to a byte read from rom.
+    pub fn read(&self, address: u16, value: u8) -> u8 {
+        for code in self.codes() {
+            if let Code::GameGenie {
+                address: a,
+                value: v,
+                compare,
+            } = *code
+            {
+                if a == address && compare.is_none_or(|c| c == value) {
+                    return v;
+                }
+            }
+        }
+        value
+    }
+
+    // The GameShark writes to apply at the end of every frame, as bank, address and value.
+    pub fn writes(&self) -> impl Iterator<Item = (u8, u16, u8)> + '_ {
+        self.codes().filter_map(|code| match *code {
+            Code::GameShark {
+                bank,
+                address,
+                value,
+            } => Some((bank, address, value)),
+            _ => None,
+        })
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("invalid cheat code {0}")]
+    InvalidCode(String),
+    #[error("unable to read cheat file: {source}")]
+    Io {
+        #[from]
+        source: io::Error,
+    },
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decodes_codes() {
+        assert_eq!(
+            Code::parse("3EA-2BF-E6E").unwrap(),
+            Code::GameGenie {
+                address: 0x0a2b,
+                value: 0x3e,
+                compare: Some(0x01),
+            }
+        );
+        assert_eq!(
+            Code::parse("01FF34C2").unwrap(),
+            Code::GameShark {
+                bank: 0x01,
+                address: 0xc234,
+                value: 0xff,
+            }
+        );
+        assert!(Code::parse("01A-2B7").is_err());
+        assert!(Code::parse("01FF34").is_err());
+        // Video ram and the rom's bank registers are out of reach.
+        assert!(Code::parse("01FF3480").is_err());
+        assert!(Code::parse("01FF0020").is_err());
+
+        let mut cheats = Cheats::new();
+        cheats.add("lives", "3EA-2BF-E6E+01FF34C2").unwrap();
+        assert_eq!(cheats.read(0x0a2b, 0x01), 0x3e);
+        assert_eq!(cheats.read(0x0a2b, 0x05), 0x05);
+        assert_eq!(cheats.writes().count(), 1);
+        assert_eq!(cheats.toggle(0), Some(false));
+        assert_eq!(cheats.writes().count(), 0);
+    }
+}
@@ -0,0 +1,369 @@
+use crate::joypad;
+use serde::{de::DeserializeOwned, Deserialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::hash::Hash;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Up,
+    Down,
+    Left,
+    Right,
+    A,
+    B,
+    Start,
+    Select,
+    Pause,
+    FrameAdvance,
+    FastForward,
+    SlowMotion,
+    Rewind,
+    TurboA,
+    TurboB,
+    Fullscreen,
+    // Tilt accelerometer cartridges fully in a direction while held.
+    TiltUp,
+    TiltDown,
+    TiltLeft,
+    TiltRight,
+    // Toggles a cheat by its position in the cheat file, bound as `cheat_1` and up.
+    Cheat(usize),
+    // Macros are bound in their own section, by their index in it.
+    Macro(usize),
+}
+
+impl Action {
+    pub fn button(self) -> Option<joypad::Button> {
+        match self {
+            Action::Up => Some(joypad::Button::Up),
+            Action::Down => Some(joypad::Button::Down),
+            Action::Left => Some(joypad::Button::Left),
+            Action::Right => Some(joypad::Button::Right),
+            Action::A => Some(joypad::Button::A),
+            Action::B => Some(joypad::Button::B),
+            Action::Start => Some(joypad::Button::Start),
+            Action::Select => Some(joypad::Button::Select),
+            _ => None,
+        }
+    }
+
+    pub fn turbo(self) -> Option<joypad::Button> {
+        match self {
+            Action::TurboA => Some(joypad::Button::A),
+            Action::TurboB => Some(joypad::Button::B),
+            _ => None,
+        }
+    }
+}
+
+// Bindings map the snake case names of actions to the names the frontend uses for keys, gamepad
+// buttons and axes, so this stays independent of the window and gamepad libraries. Bindings given
+// in the file are merged over the defaults, see `merge`.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    // Frames each turbo press and release lasts.
+    pub turbo_rate: usize,
+    pub keyboard: BTreeMap<String, String>,
+    pub gamepad: Gamepad,
+    pub macros: Vec<MacroBinding>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MacroBinding {
+    pub steps: String,
+    pub key: Option<String>,
+    pub button: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Gamepad {
+    // Stick positions closer to the center than this are ignored.
+    pub deadzone: f32,
+    pub buttons: BTreeMap<String, String>,
+    pub axes: Axes,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Axes {
+    // Pushing these past the deadzone presses the matching directions of the d-pad.
+    pub horizontal: Option<String>,
+    pub vertical: Option<String>,
+    // These tilt accelerometer cartridges.
+    pub tilt_x: Option<String>,
+    pub tilt_y: Option<String>,
+}
+
+impl Config {
+    pub fn with_path(path: &Path) -> Result<Self, Error> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    pub fn keyboard<K: DeserializeOwned + Eq + Hash>(&self) -> Result<HashMap<K, Action>, Error> {
+        bindings(&self.keyboard, self.macros.iter().map(|m| &m.key))
+    }
+
+    pub fn gamepad_buttons<B: DeserializeOwned + Eq + Hash>(
+        &self,
+    ) -> Result<HashMap<B, Action>, Error> {
+        bindings(&self.gamepad.buttons, self.macros.iter().map(|m| &m.button))
+    }
+
+    pub fn macros(&self) -> Result<Vec<joypad::Macro>, Error> {
+        self.macros
+            .iter()
+            .map(|m| m.steps.parse().map_err(|message| Error::Macro { message }))
+            .collect()
+    }
+}
+
+impl FromStr for Config {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut config: Config = toml::from_str(s)?;
+        let defaults = Config::default();
+        config.keyboard = merge(
+            defaults.keyboard,
+            std::mem::take(&mut config.keyboard),
+            config.macros.iter().map(|m| &m.key),
+        );
+        config.gamepad.buttons = merge(
+            defaults.gamepad.buttons,
+            std::mem::take(&mut config.gamepad.buttons),
+            config.macros.iter().map(|m| &m.button),
+        );
+        Ok(config)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let keyboard = [
+            ("up", "W"),
+            ("down", "S"),
+            ("left", "A"),
+            ("right", "D"),
+            ("a", "M"),
+            ("b", "N"),
+            ("start", "Z"),
+            ("select", "X"),
+            ("pause", "P"),
+            ("frame_advance", "K"),
+            ("fast_forward", "Space"),
+            ("slow_motion", "E"),
+            ("rewind", "Backspace"),
+            ("turbo_a", "J"),
+            ("turbo_b", "H"),
+            ("fullscreen", "F"),
+            ("tilt_up", "Up"),
+            ("tilt_down", "Down"),
+            ("tilt_left", "Left"),
+            ("tilt_right", "Right"),
+        ];
+        let cheats = (1..=12).map(|i| (format!("cheat_{}", i), format!("F{}", i)));
+        Self {
+            turbo_rate: joypad::DEFAULT_TURBO_RATE,
+            keyboard: keyboard
+                .iter()
+                .map(|&(action, name)| (action.to_string(), name.to_string()))
+                .chain(cheats)
+                .collect(),
+            gamepad: Gamepad::default(),
+            macros: Vec::new(),
+        }
+    }
+}
+
+impl Default for Gamepad {
+    // Face buttons follow where A and B sit on the Game Boy, rather than their labels.
+    fn default() -> Self {
+        let buttons = [
+            ("up", "DPadUp"),
+            ("down", "DPadDown"),
+            ("left", "DPadLeft"),
+            ("right", "DPadRight"),
+            ("a", "East"),
+            ("b", "South"),
+            ("start", "Start"),
+            ("select", "Select"),
+            ("pause", "Mode"),
+            ("fast_forward", "RightTrigger"),
+            ("rewind", "LeftTrigger"),
+            ("slow_motion", "LeftTrigger2"),
+            ("turbo_a", "North"),
+            ("turbo_b", "West"),
+        ];
+        Self {
+            deadzone: 0.3,
+            buttons: buttons
+                .iter()
+                .map(|&(action, name)| (action.to_string(), name.to_string()))
+                .collect(),
+            axes: Axes::default(),
+        }
+    }
+}
+
+impl Default for Axes {
+    fn default() -> Self {
+        Self {
+            horizontal: Some("LeftStickX".to_string()),
+            vertical: Some("LeftStickY".to_string()),
+            tilt_x: Some("RightStickX".to_string()),
+            tilt_y: Some("RightStickY".to_string()),
+        }
+    }
+}
+
+// Looks up an action by its snake case name, counting cheats from 1 like the function keys.
+fn action(name: &str) -> Result<Action, Error> {
+    let cheat = name
+        .strip_prefix("cheat_")
+        .and_then(|i| i.parse::<usize>().ok())
+        .filter(|&i| i > 0);
+    match cheat {
+        Some(i) => Ok(Action::Cheat(i - 1)),
+        None => resolve(name).map_err(|_| Error::UnknownAction {
+            name: name.to_string(),
+        }),
+    }
+}
+
+// Looks up a key, button or axis by the name its library gives the enum variant.
+pub fn resolve<T: DeserializeOwned>(name: &str) -> Result<T, Error> {
+    T::deserialize(toml::Value::String(name.to_string())).map_err(|_| Error::UnknownName {
+        name: name.to_string(),
+    })
+}
+
+// Each binding given replaces the default binding of its action, and takes its key or button
+// away from the action it was bound to by default. An empty name leaves the action unbound.
+fn merge<'a>(
+    defaults: BTreeMap<String, String>,
+    given: BTreeMap<String, String>,
+    macros: impl Iterator<Item = &'a Option<String>>,
+) -> BTreeMap<String, String> {
+    let taken = given
+        .values()
+        .cloned()
+        .chain(macros.flatten().cloned())
+        .collect::<HashSet<_>>();
+    let mut merged = defaults
+        .into_iter()
+        .filter(|(_, name)| !taken.contains(name))
+        .collect::<BTreeMap<_, _>>();
+    merged.extend(given);
+    merged.retain(|_, name| !name.is_empty());
+    merged
+}
+
+fn bindings<'a, T: DeserializeOwned + Eq + Hash>(
+    actions: &BTreeMap<String, String>,
+    macros: impl Iterator<Item = &'a Option<String>>,
+) -> Result<HashMap<T, Action>, Error> {
+    let mut bindings = HashMap::new();
+    let mut bind = |name: &String, action| {
+        if bindings.insert(resolve(name)?, action).is_some() {
+            return Err(Error::Conflict { name: name.clone() });
+        }
+        Ok(())
+    };
+    for (action, name) in actions {
+        bind(name, self::action(action)?)?;
+    }
+    for (i, name) in macros.enumerate() {
+        if let Some(name) = name {
+            bind(name, Action::Macro(i))?;
+        }
+    }
+    Ok(bindings)
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("unknown action {name}")]
+    UnknownAction { name: String },
+    #[error("invalid macro: {message}")]
+    Macro { message: String },
+    #[error("unknown key, button or axis {name}")]
+    UnknownName { name: String },
+    #[error("{name} is bound more than once")]
+    Conflict { name: String },
+    #[error("invalid config: {source}")]
+    Toml {
+        #[from]
+        source: toml::de::Error,
+    },
+    #[error("io error: {source}")]
+    Io {
+        #[from]
+        source: io::Error,
+    },
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reads_bindings() {
+        let config: Config = r#"
+            [keyboard]
+            a = "J"
+            rewind = "R"
+
+            [gamepad]
+            deadzone = 0.5
+
+            [[macros]]
+            steps = "down right+a"
+            key = "Q"
+        "#
+        .parse()
+        .unwrap();
+        let keyboard = config.keyboard::<String>().unwrap();
+        // J moves from turbo A over to A, and M and Backspace are left unbound.
+        assert_eq!(keyboard.len(), Config::default().keyboard.len());
+        assert_eq!(keyboard["J"], Action::A);
+        assert_eq!(keyboard["R"], Action::Rewind);
+        assert_eq!(keyboard["W"], Action::Up);
+        assert_eq!(keyboard["Q"], Action::Macro(0));
+        assert!(!keyboard.contains_key("M") && !keyboard.contains_key("Backspace"));
+        assert_eq!(keyboard["Up"], Action::TiltUp);
+        assert_eq!(keyboard["F3"], Action::Cheat(2));
+        assert_eq!(config.macros().unwrap().len(), 1);
+        assert_eq!(config.gamepad.deadzone, 0.5);
+        assert_eq!(
+            config.gamepad_buttons::<String>().unwrap()["East"],
+            Action::A
+        );
+        assert_eq!(
+            resolve::<Action>("fast_forward").unwrap(),
+            Action::FastForward
+        );
+
+        let config: Config = "[keyboard]\njump = \"W\"\n".parse().unwrap();
+        assert!(config.keyboard::<String>().is_err());
+        let config: Config = "[keyboard]\na = \"J\"\nb = \"J\"\n".parse().unwrap();
+        assert!(config.keyboard::<String>().is_err());
+        let config: Config = "[keyboard]\ncheat_0 = \"C\"\n".parse().unwrap();
+        assert!(config.keyboard::<String>().is_err());
+        // Arrow keys and function keys are checked for conflicts like any other binding.
+        let config: Config = "[[macros]]\nsteps = \"a\"\nkey = \"F1\"\n".parse().unwrap();
+        assert!(config.keyboard::<String>().is_err());
+        let config: Config = "[gamepad.buttons]\npause = \"\"\n".parse().unwrap();
+        let buttons = config.gamepad_buttons::<String>().unwrap();
+        assert!(!buttons.contains_key("Mode"));
+        assert_eq!(buttons["East"], Action::A);
+    }
+}
@@ -1,13 +1,21 @@
 use crate::bytes;
 use crate::cart::GBType;
+use crate::cheats::Cheats;
 use crate::joypad::Joypad;
 use crate::lcd::LCD;
 use crate::memory::Memory;
+use crate::movie;
+use crate::state;
 use crate::timer;
+use serde::{Deserialize, Serialize};
 
 use std::io::Write;
 
+// The memory is captured on its own, as it holds the cartridge. The frontend hooks are left
+// out of the state entirely.
+#[derive(Serialize, Deserialize)]
 pub struct CPU {
+    #[serde(skip)]
     memory: Memory,
     lcd: LCD,
     joypad: Joypad,
@@ -29,7 +37,15 @@ pub struct CPU {
     extra_timing: timer::Timing,
     prepare_speed: bool,
 
+    #[serde(skip)]
     show_serial_output: bool,
+
+    rumble: bool,
+    #[serde(skip)]
+    on_rumble: Option<Box<dyn FnMut(bool)>>,
+
+    #[serde(skip)]
+    movie: Option<movie::Session>,
 }
 
 impl CPU {
@@ -54,6 +70,9 @@ impl CPU {
             extra_timing: 0,
             prepare_speed: false,
             show_serial_output: false,
+            rumble: false,
+            on_rumble: None,
+            movie: None,
         };
         if !has_bootrom {
             cpu.reset();
@@ -108,18 +127,26 @@ impl CPU {
     }
 
     pub fn cycle(&mut self) {
+        self.joypad.frame();
+        if let Some(movie) = self.movie.as_mut() {
+            movie.frame(&mut self.joypad);
+        }
         loop {
             self.step();
             if self.lcd.done_frame() {
                 break;
             }
         }
+        self.memory.apply_cheats();
     }
 
     pub fn step(&mut self) {
         if self.joypad.check_interrupts() {
             self.interrupts.flag |= 0x10;
         }
+        if let Some((x, y)) = self.joypad.check_tilt() {
+            self.memory.tilt(x, y);
+        }
         let timing = if let Some(timing) = self.handle_interrupts() {
             timing
         } else if self.halt {
@@ -138,6 +165,7 @@ impl CPU {
 
     fn advance_timer(&mut self, timing: timer::Timing) {
         self.extra_timing += timing;
+        self.memory.advance(timing / self.speed);
         if self.timer.advance(timing * self.speed) {
             self.interrupts.flag |= 0x04;
         }
@@ -147,10 +175,63 @@ impl CPU {
         &self.lcd
     }
 
+    pub fn save_state(&self) -> Vec<u8> {
+        state::to_bytes(&(self, self.memory.save_state()))
+    }
+
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), state::Error> {
+        let (mut cpu, memory): (CPU, Vec<u8>) = state::from_bytes(data)?;
+        self.memory.load_state(&memory)?;
+        std::mem::swap(&mut self.memory, &mut cpu.memory);
+        cpu.joypad.take_input(&mut self.joypad);
+        cpu.show_serial_output = self.show_serial_output;
+        cpu.on_rumble = self.on_rumble.take();
+        cpu.movie = self.movie.take();
+        let rumble = self.rumble;
+        *self = cpu;
+        // The motor follows the restored state like it would a write to the cartridge.
+        if self.rumble != rumble {
+            if let Some(callback) = self.on_rumble.as_mut() {
+                callback(self.rumble);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn set_movie(&mut self, movie: movie::Session) {
+        self.movie = Some(movie);
+    }
+
+    pub fn movie(&self) -> Option<&movie::Session> {
+        self.movie.as_ref()
+    }
+
+    pub fn take_movie(&mut self) -> Option<movie::Session> {
+        self.movie.take()
+    }
+
+    // While a movie plays back, the joypad is not for the player to press.
+    pub fn playing_movie(&self) -> bool {
+        self.movie.as_ref().is_some_and(|movie| movie.is_playing())
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
     pub fn joypad(&mut self) -> &mut Joypad {
         &mut self.joypad
     }
 
+    pub fn cheats(&mut self) -> &mut Cheats {
+        self.memory.cheats()
+    }
+
+    // Called with the new motor state whenever a rumble cartridge switches it on or off.
+    pub fn on_rumble(&mut self, callback: impl FnMut(bool) + 'static) {
+        self.on_rumble = Some(Box::new(callback));
+    }
+
     #[allow(dead_code)]
     pub fn show_serial_output(&mut self, output: bool) {
         self.show_serial_output = output;
@@ -264,11 +345,27 @@ impl CPU {
                 }
             }
             0xffff => self.interrupts.enable = value & 0x1f,
+            0x4000..=0x5fff => {
+                self.memory.write(address, value);
+                self.update_rumble();
+            }
             _ => self.memory.write(address, value),
         }
     }
+
+    fn update_rumble(&mut self) {
+        let rumble = self.memory.rumble();
+        if rumble == self.rumble {
+            return;
+        }
+        self.rumble = rumble;
+        if let Some(callback) = self.on_rumble.as_mut() {
+            callback(rumble);
+        }
+    }
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct Interrupts {
     pub enabled: bool,
     pub enable: u8,
@@ -285,7 +382,7 @@ impl Default for Interrupts {
     }
 }
 
-#[derive(Default, Clone, Copy)]
+#[derive(Default, Clone, Copy, Serialize, Deserialize)]
 pub struct Registers {
     pub a: u8,
     pub f: Flags,
@@ -348,7 +445,7 @@ impl Registers {
     }
 }
 
-#[derive(Clone, Copy, Default)]
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
 pub struct Flags {
     pub zero: bool,
     pub add_sub: bool,
@@ -1329,4 +1426,36 @@ mod test {
         assert_eq!(cpu.regs.f.half_carry, true);
         assert_eq!(cpu.regs.f.carry, true);
     }
+
+    #[test]
+    fn restores_state() {
+        let memory = Memory::with_cartridge(crate::mbc::test::rom(0x03, 4, 0x02)).unwrap();
+        let mut cpu = CPU::new(memory, LCD::new(GBType::NonCGB));
+        cpu.write(0x0000, 0x0a);
+        cpu.write(0xa000, 0x12);
+        cpu.write(0xc000, 0x34);
+        cpu.cycle();
+        let state = cpu.save_state();
+        let pc = cpu.pc;
+
+        cpu.write(0x4000, 0x01);
+        cpu.write(0xa000, 0x00);
+        cpu.write(0xc000, 0x00);
+        cpu.cycle();
+        cpu.load_state(&state).unwrap();
+        assert_eq!(cpu.pc, pc);
+        assert_eq!(cpu.read(0xa000), 0x12);
+        assert_eq!(cpu.read(0xc000), 0x34);
+        assert_eq!(cpu.save_state(), state);
+
+        let rumble = std::rc::Rc::new(std::cell::Cell::new(None));
+        let set = rumble.clone();
+        cpu.on_rumble(move |on| set.set(Some(on)));
+        cpu.rumble = true;
+        cpu.load_state(&state).unwrap();
+        assert_eq!(rumble.get(), Some(false));
+        rumble.set(None);
+        cpu.load_state(&state).unwrap();
+        assert_eq!(rumble.get(), None);
+    }
 }
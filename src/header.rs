@@ -0,0 +1,436 @@
+use crate::cart::Error;
+use serde::Serialize;
+use std::fmt;
+
+pub const NINTENDO_LOGO: [u8; 0x30] = [
+    0xce, 0xed, 0x66, 0x66, 0xcc, 0x0d, 0x00, 0x0b, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0c, 0x00, 0x0d,
+    0x00, 0x08, 0x11, 0x1f, 0x88, 0x89, 0x00, 0x0e, 0xdc, 0xcc, 0x6e, 0xe6, 0xdd, 0xdd, 0xd9, 0x99,
+    0xbb, 0xbb, 0x67, 0x63, 0x6e, 0x0e, 0xec, 0xcc, 0xdd, 0xdc, 0x99, 0x9f, 0xbb, 0xb9, 0x33, 0x3e,
+];
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Header {
+    pub title: String,
+    pub manufacturer_code: Option<String>,
+    pub cgb_flag: u8,
+    pub sgb: bool,
+    pub cart_type: u8,
+    pub licensee: Licensee,
+    pub rom_size: u8,
+    pub rom_banks: Option<usize>,
+    pub ram_size: u8,
+    pub destination: Destination,
+    pub mask_rom_version: u8,
+    pub header_checksum: u8,
+    pub header_checksum_valid: bool,
+    pub global_checksum: u16,
+    pub global_checksum_valid: bool,
+    pub logo_valid: bool,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Licensee {
+    pub old_code: u8,
+    pub new_code: Option<String>,
+    pub name: Option<&'static str>,
+}
+
+#[derive(Debug, Copy, Clone, Default, Serialize)]
+pub enum Destination {
+    #[default]
+    Japan,
+    Overseas,
+    Unknown(u8),
+}
+
+impl Header {
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        if data.len() < 0x0150 {
+            return Err(Error::InvalidRom);
+        }
+        let cgb_flag = data[0x0143];
+        // Newer cartridges shortened the title to make space for the manufacturer code and
+        // the CGB flag, older ones use the whole area for the title.
+        let manufacturer_code = if cgb_flag & 0x80 != 0 {
+            let code = &data[0x013f..0x0143];
+            if code
+                .iter()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+            {
+                Some(String::from_utf8_lossy(code).to_string())
+            } else {
+                None
+            }
+        } else {
+            None
+        };
+        let title_end = match (cgb_flag & 0x80 != 0, &manufacturer_code) {
+            (_, Some(_)) => 0x013f,
+            (true, None) => 0x0143,
+            (false, None) => 0x0144,
+        };
+        let title = String::from_utf8_lossy(&data[0x0134..title_end])
+            .trim_end_matches(char::from(0))
+            .to_string();
+
+        let old_code = data[0x014b];
+        let licensee = if old_code == 0x33 {
+            let code = String::from_utf8_lossy(&data[0x0144..0x0146]).to_string();
+            Licensee {
+                old_code,
+                name: new_licensee_name(&code),
+                new_code: Some(code),
+            }
+        } else {
+            Licensee {
+                old_code,
+                new_code: None,
+                name: old_licensee_name(old_code),
+            }
+        };
+
+        let rom_size = data[0x0148];
+        let header_checksum = data[0x014d];
+        let global_checksum = ((data[0x014e] as u16) << 8) | data[0x014f] as u16;
+        Ok(Self {
+            title,
+            manufacturer_code,
+            cgb_flag,
+            sgb: data[0x0146] == 0x03,
+            cart_type: data[0x0147],
+            licensee,
+            rom_size,
+            rom_banks: match rom_size {
+                0x00..=0x08 => Some(2 << rom_size),
+                _ => None,
+            },
+            ram_size: data[0x0149],
+            destination: match data[0x014a] {
+                0x00 => Destination::Japan,
+                0x01 => Destination::Overseas,
+                d => Destination::Unknown(d),
+            },
+            mask_rom_version: data[0x014c],
+            header_checksum,
+            header_checksum_valid: Self::header_checksum(data) == header_checksum,
+            global_checksum,
+            global_checksum_valid: Self::global_checksum(data) == global_checksum,
+            logo_valid: data[0x0104..0x0134] == NINTENDO_LOGO,
+        })
+    }
+
+    pub fn ram_bytes(&self) -> usize {
+        match self.ram_size {
+            0x01 => 0x800,
+            0x02 => 0x2000,
+            0x03 => 0x8000,
+            0x04 => 0x20000,
+            0x05 => 0x10000,
+            _ => 0,
+        }
+    }
+
+    pub fn header_checksum(data: &[u8]) -> u8 {
+        data[0x0134..=0x014c]
+            .iter()
+            .fold(0u8, |x, i| x.wrapping_sub(*i).wrapping_sub(1))
+    }
+
+    pub fn global_checksum(data: &[u8]) -> u16 {
+        data.iter()
+            .enumerate()
+            .filter(|(i, _)| *i != 0x014e && *i != 0x014f)
+            .fold(0u16, |x, (_, b)| x.wrapping_add(*b as u16))
+    }
+}
+
+impl fmt::Display for Header {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let valid = |v: bool| if v { "ok" } else { "invalid" };
+        writeln!(f, "Title:            {}", self.title)?;
+        if let Some(code) = &self.manufacturer_code {
+            writeln!(f, "Manufacturer:     {}", code)?;
+        }
+        writeln!(f, "CGB flag:         {:#04x}", self.cgb_flag)?;
+        writeln!(f, "SGB support:      {}", self.sgb)?;
+        writeln!(f, "Cartridge type:   {:#04x}", self.cart_type)?;
+        match &self.licensee.new_code {
+            Some(code) => write!(f, "Licensee:         {} (new)", code)?,
+            None => write!(f, "Licensee:         {:#04x} (old)", self.licensee.old_code)?,
+        }
+        writeln!(f, " {}", self.licensee.name.unwrap_or("unknown"))?;
+        match self.rom_banks {
+            Some(banks) => writeln!(
+                f,
+                "ROM size:         {:#04x} ({} KiB, {} banks)",
+                self.rom_size,
+                banks * 16,
+                banks
+            )?,
+            None => writeln!(f, "ROM size:         {:#04x} (unknown)", self.rom_size)?,
+        }
+        writeln!(
+            f,
+            "RAM size:         {:#04x} ({} KiB)",
+            self.ram_size,
+            self.ram_bytes() / 1024
+        )?;
+        writeln!(f, "Destination:      {:?}", self.destination)?;
+        writeln!(f, "Mask ROM version: {}", self.mask_rom_version)?;
+        writeln!(
+            f,
+            "Header checksum:  {:#04x} ({})",
+            self.header_checksum,
+            valid(self.header_checksum_valid)
+        )?;
+        writeln!(
+            f,
+            "Global checksum:  {:#06x} ({})",
+            self.global_checksum,
+            valid(self.global_checksum_valid)
+        )?;
+        write!(f, "Nintendo logo:    {}", valid(self.logo_valid))
+    }
+}
+
+fn new_licensee_name(code: &str) -> Option<&'static str> {
+    Some(match code {
+        "00" => "None",
+        "01" => "Nintendo R&D1",
+        "08" => "Capcom",
+        "13" => "Electronic Arts",
+        "18" => "Hudson Soft",
+        "19" => "b-ai",
+        "20" => "kss",
+        "22" => "pow",
+        "24" => "PCM Complete",
+        "25" => "san-x",
+        "28" => "Kemco Japan",
+        "29" => "seta",
+        "30" => "Viacom",
+        "31" => "Nintendo",
+        "32" => "Bandai",
+        "33" => "Ocean/Acclaim",
+        "34" => "Konami",
+        "35" => "Hector",
+        "37" => "Taito",
+        "38" => "Hudson",
+        "39" => "Banpresto",
+        "41" => "Ubi Soft",
+        "42" => "Atlus",
+        "44" => "Malibu",
+        "46" => "angel",
+        "47" => "Bullet-Proof",
+        "49" => "irem",
+        "50" => "Absolute",
+        "51" => "Acclaim",
+        "52" => "Activision",
+        "53" => "American sammy",
+        "54" => "Konami",
+        "55" => "Hi tech entertainment",
+        "56" => "LJN",
+        "57" => "Matchbox",
+        "58" => "Mattel",
+        "59" => "Milton Bradley",
+        "60" => "Titus",
+        "61" => "Virgin",
+        "64" => "LucasArts",
+        "67" => "Ocean",
+        "69" => "Electronic Arts",
+        "70" => "Infogrames",
+        "71" => "Interplay",
+        "72" => "Broderbund",
+        "73" => "sculptured",
+        "75" => "sci",
+        "78" => "THQ",
+        "79" => "Accolade",
+        "80" => "misawa",
+        "83" => "lozc",
+        "86" => "Tokuma Shoten Intermedia",
+        "87" => "Tsukuda Original",
+        "91" => "Chunsoft",
+        "92" => "Video system",
+        "93" => "Ocean/Acclaim",
+        "95" => "Varie",
+        "96" => "Yonezawa/s'pal",
+        "97" => "Kaneko",
+        "99" => "Pack in soft",
+        "A4" => "Konami (Yu-Gi-Oh!)",
+        _ => return None,
+    })
+}
+
+fn old_licensee_name(code: u8) -> Option<&'static str> {
+    Some(match code {
+        0x00 => "None",
+        0x01 | 0x31 => "Nintendo",
+        0x08 | 0x38 => "Capcom",
+        0x09 => "Hot-B",
+        0x0a | 0xe0 => "Jaleco",
+        0x0b => "Coconuts Japan",
+        0x0c | 0x6e => "Elite Systems",
+        0x13 | 0x69 => "Electronic Arts",
+        0x18 => "Hudson Soft",
+        0x19 => "ITC Entertainment",
+        0x1a => "Yanoman",
+        0x1d => "Japan Clary",
+        0x1f | 0x4a | 0x61 => "Virgin Interactive",
+        0x24 => "PCM Complete",
+        0x25 => "San-X",
+        0x28 => "Kotobuki Systems",
+        0x29 => "Seta",
+        0x30 | 0x70 => "Infogrames",
+        0x32 | 0xa2 | 0xb2 => "Bandai",
+        0x34 | 0xa4 => "Konami",
+        0x35 => "HectorSoft",
+        0x39 | 0x9d | 0xd9 => "Banpresto",
+        0x3c => "Entertainment i",
+        0x3e => "Gremlin",
+        0x41 => "Ubi Soft",
+        0x42 | 0xeb => "Atlus",
+        0x44 | 0x4d => "Malibu",
+        0x46 | 0xcf => "Angel",
+        0x47 => "Spectrum Holoby",
+        0x49 => "Irem",
+        0x4f => "U.S. Gold",
+        0x50 => "Absolute",
+        0x51 | 0xb0 => "Acclaim",
+        0x52 => "Activision",
+        0x53 => "American Sammy",
+        0x54 => "GameTek",
+        0x55 => "Park Place",
+        0x56 | 0xdb | 0xff => "LJN",
+        0x57 => "Matchbox",
+        0x59 => "Milton Bradley",
+        0x5a => "Mindscape",
+        0x5b => "Romstar",
+        0x5c | 0xd6 => "Naxat Soft",
+        0x5d => "Tradewest",
+        0x60 => "Titus",
+        0x67 => "Ocean",
+        0x6f => "Electro Brain",
+        0x71 => "Interplay",
+        0x72 | 0xaa => "Broderbund",
+        0x73 => "Sculptered Soft",
+        0x75 => "The Sales Curve",
+        0x78 => "THQ",
+        0x79 => "Accolade",
+        0x7a => "Triffix Entertainment",
+        0x7c => "Microprose",
+        0x7f | 0xc2 => "Kemco",
+        0x80 => "Misawa Entertainment",
+        0x83 => "Lozc",
+        0x86 | 0xc4 => "Tokuma Shoten Intermedia",
+        0x8b => "Bullet-Proof Software",
+        0x8c => "Vic Tokai",
+        0x8e => "Ape",
+        0x8f => "I'Max",
+        0x91 => "Chunsoft",
+        0x92 => "Video System",
+        0x93 => "Tsubaraya Productions",
+        0x95 | 0xe3 => "Varie",
+        0x96 => "Yonezawa/S'Pal",
+        0x97 => "Kaneko",
+        0x99 => "Arc",
+        0x9a => "Nihon Bussan",
+        0x9b => "Tecmo",
+        0x9c => "Imagineer",
+        0x9f => "Nova",
+        0xa1 => "Hori Electric",
+        0xa6 => "Kawada",
+        0xa7 => "Takara",
+        0xa9 => "Technos Japan",
+        0xac => "Toei Animation",
+        0xad => "Toho",
+        0xaf => "Namco",
+        0xb1 => "ASCII or Nexsoft",
+        0xb4 => "Square Enix",
+        0xb6 => "HAL Laboratory",
+        0xb7 => "SNK",
+        0xb9 | 0xce => "Pony Canyon",
+        0xba => "Culture Brain",
+        0xbb => "Sunsoft",
+        0xbd => "Sony Imagesoft",
+        0xbf => "Sammy",
+        0xc0 | 0xd0 => "Taito",
+        0xc3 => "Squaresoft",
+        0xc5 => "Data East",
+        0xc6 => "Tonkinhouse",
+        0xc8 => "Koei",
+        0xc9 => "UFL",
+        0xca => "Ultra",
+        0xcb => "Vap",
+        0xcc => "Use Corporation",
+        0xcd => "Meldac",
+        0xd1 => "Sofel",
+        0xd2 => "Quest",
+        0xd3 => "Sigma Enterprises",
+        0xd4 => "ASK Kodansha",
+        0xd7 => "Copya System",
+        0xda => "Tomy",
+        0xdd => "NCS",
+        0xde => "Human",
+        0xdf => "Altron",
+        0xe1 => "Towa Chiki",
+        0xe2 => "Yutaka",
+        0xe5 => "Epoch",
+        0xe7 => "Athena",
+        0xe8 => "Asmik Ace Entertainment",
+        0xe9 => "Natsume",
+        0xea => "King Records",
+        0xec => "Epic/Sony Records",
+        0xee => "IGS",
+        0xf0 => "A Wave",
+        0xf3 => "Extreme Entertainment",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rom() -> Vec<u8> {
+        let mut data = vec![0; 0x8000];
+        data[0x0104..0x0134].copy_from_slice(&NINTENDO_LOGO);
+        data[0x0134..0x013f].copy_from_slice(b"POKEMON_SLV");
+        data[0x013f..0x0143].copy_from_slice(b"AAXE");
+        data[0x0143] = 0x80;
+        data[0x0144..0x0146].copy_from_slice(b"01");
+        data[0x0146] = 0x03;
+        data[0x0147] = 0x10;
+        data[0x0149] = 0x03;
+        data[0x014a] = 0x01;
+        data[0x014b] = 0x33;
+        data[0x014d] = Header::header_checksum(&data);
+        let global = Header::global_checksum(&data);
+        data[0x014e] = (global >> 8) as u8;
+        data[0x014f] = global as u8;
+        data
+    }
+
+    #[test]
+    fn parse() {
+        let header = Header::parse(&rom()).unwrap();
+        assert_eq!(header.title, "POKEMON_SLV");
+        assert_eq!(header.manufacturer_code.as_deref(), Some("AAXE"));
+        assert_eq!(header.licensee.name, Some("Nintendo R&D1"));
+        assert_eq!(header.rom_banks, Some(2));
+        assert_eq!(header.ram_bytes(), 0x8000);
+        assert!(header.sgb);
+        assert!(header.logo_valid);
+        assert!(header.header_checksum_valid);
+        assert!(header.global_checksum_valid);
+    }
+
+    #[test]
+    fn invalid_checksums() {
+        let mut data = rom();
+        data[0x0104] = 0;
+        data[0x0200] = 0xff;
+        let header = Header::parse(&data).unwrap();
+        assert!(!header.logo_valid);
+        assert!(header.header_checksum_valid);
+        assert!(!header.global_checksum_valid);
+    }
+}
@@ -1,8 +1,90 @@
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+#[derive(Serialize, Deserialize)]
 pub struct Joypad {
-    selection: Option<Selection>,
+    // The buttons sit in a matrix, with the directions on P14 and the others on P15. Writing a
+    // low bit to either selects it, and pressed buttons in selected rows pull their line low.
+    select: u8,
     buttons: [bool; 8],
-    flag: u8,
     interrupts: bool,
+
+    // Analog tilt for accelerometer cartridges, -1.0 to 1.0 on each axis.
+    tilt: (f32, f32),
+    tilt_changed: bool,
+
+    // What the player holds is left out of machine states, so loading one keeps it.
+    #[serde(skip)]
+    input: Input,
+}
+
+pub const DEFAULT_TURBO_RATE: usize = 2;
+
+// The buttons are pressed when the player holds them, or when a held turbo button or a playing
+// macro presses them for the current frame.
+struct Input {
+    held: [bool; 8],
+    // Frames since each turbo button was pressed.
+    turbo: [Option<usize>; 8],
+    // Frames each turbo press and release lasts.
+    turbo_rate: usize,
+    pulse: u8,
+    playing: Option<(Macro, usize)>,
+    step: u8,
+    // A real d-pad cannot press opposing directions, and some games break when they are.
+    allow_opposing: bool,
+}
+
+impl Default for Input {
+    fn default() -> Self {
+        Self {
+            held: [false; 8],
+            turbo: [None; 8],
+            turbo_rate: DEFAULT_TURBO_RATE,
+            pulse: 0,
+            playing: None,
+            step: 0,
+            allow_opposing: false,
+        }
+    }
+}
+
+// The buttons to hold on each frame, written as steps separated by whitespace. A step holds
+// buttons joined with `+`, or none for `_`, for the number of frames after a `*`, or one frame
+// by default. `down*4 down+right*4 right+a` is a fireball motion.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Macro {
+    frames: Vec<u8>,
+}
+
+impl FromStr for Macro {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut frames = Vec::new();
+        for step in s.split_whitespace() {
+            let (buttons, count) = match step.split_once('*') {
+                Some((buttons, count)) => (
+                    buttons,
+                    count
+                        .parse()
+                        .map_err(|_| format!("invalid frame count in {}", step))?,
+                ),
+                None => (step, 1),
+            };
+            let mut mask = 0;
+            if buttons != "_" {
+                for button in buttons.split('+') {
+                    mask |= 1 << button.parse::<Button>()? as usize;
+                }
+            }
+            frames.extend(std::iter::repeat_n(mask, count));
+        }
+        if frames.is_empty() {
+            return Err("empty macro".to_string());
+        }
+        Ok(Self { frames })
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -17,20 +99,156 @@ pub enum Button {
     Select,
 }
 
-#[derive(Debug, Clone, Copy)]
-enum Selection {
-    Direction,
-    Buttons,
+const BUTTONS: [Button; 8] = [
+    Button::Up,
+    Button::Down,
+    Button::Left,
+    Button::Right,
+    Button::A,
+    Button::B,
+    Button::Start,
+    Button::Select,
+];
+
+impl FromStr for Button {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_lowercase().as_str() {
+            "up" => Button::Up,
+            "down" => Button::Down,
+            "left" => Button::Left,
+            "right" => Button::Right,
+            "a" => Button::A,
+            "b" => Button::B,
+            "start" => Button::Start,
+            "select" => Button::Select,
+            _ => return Err(format!("unknown button {}", s)),
+        })
+    }
+}
+
+const SELECT_DIRECTIONS: u8 = 0x10;
+const SELECT_BUTTONS: u8 = 0x20;
+
+impl Button {
+    // The select bit of the row the button sits in, and the line it pulls low.
+    fn line(self) -> (u8, u8) {
+        match self {
+            Button::Right => (SELECT_DIRECTIONS, 0x01),
+            Button::Left => (SELECT_DIRECTIONS, 0x02),
+            Button::Up => (SELECT_DIRECTIONS, 0x04),
+            Button::Down => (SELECT_DIRECTIONS, 0x08),
+            Button::A => (SELECT_BUTTONS, 0x01),
+            Button::B => (SELECT_BUTTONS, 0x02),
+            Button::Select => (SELECT_BUTTONS, 0x04),
+            Button::Start => (SELECT_BUTTONS, 0x08),
+        }
+    }
 }
 
 impl Joypad {
     pub fn new() -> Self {
         Self {
-            selection: None,
+            select: SELECT_DIRECTIONS | SELECT_BUTTONS,
             buttons: [false; 8],
-            flag: 0xff,
             interrupts: false,
+            tilt: (0.0, 0.0),
+            tilt_changed: false,
+
+            input: Input::default(),
+        }
+    }
+
+    // Moves the input of the player over from another joypad.
+    pub fn take_input(&mut self, other: &mut Joypad) {
+        self.input = std::mem::take(&mut other.input);
+    }
+
+    pub fn set_allow_opposing(&mut self, allow: bool) {
+        self.input.allow_opposing = allow;
+        self.update();
+    }
+
+    pub fn set_turbo_rate(&mut self, frames: usize) {
+        self.input.turbo_rate = frames.max(1);
+    }
+
+    // Turbo buttons and macros start pressing on the next frame.
+    pub fn press_turbo(&mut self, button: Button) {
+        let turbo = &mut self.input.turbo[button as usize];
+        if turbo.is_none() {
+            *turbo = Some(0);
+        }
+    }
+
+    pub fn release_turbo(&mut self, button: Button) {
+        self.input.turbo[button as usize] = None;
+        self.input.pulse &= !(1 << button as usize);
+        self.update();
+    }
+
+    pub fn play_macro(&mut self, steps: &Macro) {
+        self.input.playing = Some((steps.clone(), 0));
+    }
+
+    // Called at the start of every frame to move turbo buttons and macros along.
+    pub fn frame(&mut self) {
+        let input = &mut self.input;
+        input.pulse = 0;
+        for (i, turbo) in input.turbo.iter_mut().enumerate() {
+            if let Some(frames) = turbo {
+                if (*frames / input.turbo_rate).is_multiple_of(2) {
+                    input.pulse |= 1 << i;
+                }
+                *frames += 1;
+            }
+        }
+        input.step = 0;
+        if let Some((steps, frame)) = input.playing.as_mut() {
+            input.step = steps.frames[*frame];
+            *frame += 1;
+            if *frame == steps.frames.len() {
+                input.playing = None;
+            }
+        }
+        self.update();
+    }
+
+    fn update(&mut self) {
+        let lines = self.lines();
+        let mask = self.input.pulse | self.input.step;
+        for &button in BUTTONS.iter() {
+            let i = button as usize;
+            self.buttons[i] = self.input.held[i] || mask & (1 << i) != 0;
+        }
+        // Opposing directions cancel out, rather than favouring one of them.
+        if !self.input.allow_opposing {
+            for &(a, b) in &[(Button::Up, Button::Down), (Button::Left, Button::Right)] {
+                if self.buttons[a as usize] && self.buttons[b as usize] {
+                    self.buttons[a as usize] = false;
+                    self.buttons[b as usize] = false;
+                }
+            }
         }
+        self.check_falling(lines);
+    }
+
+    // The interrupt fires whenever a line goes low, whether from a press or a newly selected
+    // row.
+    fn check_falling(&mut self, before: u8) {
+        if before & !self.lines() != 0 {
+            self.interrupts = true;
+        }
+    }
+
+    fn lines(&self) -> u8 {
+        BUTTONS
+            .iter()
+            .filter(|&&button| self.buttons[button as usize])
+            .map(|&button| button.line())
+            .filter(|&(select, _)| self.select & select == 0)
+            .fold(0x0f, |lines, (_, line)| lines & !line)
     }
 
     pub fn check_interrupts(&mut self) -> bool {
@@ -39,80 +257,120 @@ impl Joypad {
         i
     }
 
-    pub fn press(&mut self, button: Button) {
-        if !self.buttons[button as usize] {
-            self.buttons[button as usize] = true;
-            match self.selection {
-                Some(Selection::Direction) => {
-                    if button as usize <= 3 {
-                        self.interrupts = true;
-                    }
-                }
-                Some(Selection::Buttons) => {
-                    if button as usize > 3 {
-                        self.interrupts = true;
-                    }
-                }
-                None => {}
-            }
+    pub fn tilt(&mut self, x: f32, y: f32) {
+        let tilt = (x.clamp(-1.0, 1.0), y.clamp(-1.0, 1.0));
+        if tilt != self.tilt {
+            self.tilt = tilt;
+            self.tilt_changed = true;
+        }
+    }
+
+    pub fn tilt_position(&self) -> (f32, f32) {
+        self.tilt
+    }
+
+    pub fn check_tilt(&mut self) -> Option<(f32, f32)> {
+        if !self.tilt_changed {
+            return None;
         }
+        self.tilt_changed = false;
+        Some(self.tilt)
+    }
+
+    pub fn press(&mut self, button: Button) {
+        self.input.held[button as usize] = true;
+        self.update();
     }
 
     pub fn release(&mut self, button: Button) {
-        self.buttons[button as usize] = false;
+        self.input.held[button as usize] = false;
+        self.update();
     }
 
-    pub fn select(&mut self, flag: u8) {
-        self.selection = match flag & 0x30 {
-            0x10 => Some(Selection::Buttons),
-            0x20 => Some(Selection::Direction),
-            _ => None,
-        };
-        self.flag = (self.flag & 0xcf) | (flag & 0x30);
+    // The pressed buttons as a bit mask, indexed like `Button`.
+    pub fn buttons(&self) -> u8 {
+        self.buttons
+            .iter()
+            .enumerate()
+            .fold(0, |mask, (i, &held)| mask | ((held as u8) << i))
     }
 
-    pub fn value(&self) -> u8 {
-        (self.flag & 0xf0)
-            | match self.selection {
-                Some(Selection::Direction) => {
-                    (if !self.buttons[Button::Right as usize] {
-                        0x01
-                    } else {
-                        0
-                    }) | (if !self.buttons[Button::Left as usize] {
-                        0x02
-                    } else {
-                        0
-                    }) | (if !self.buttons[Button::Up as usize] {
-                        0x04
-                    } else {
-                        0
-                    }) | (if !self.buttons[Button::Down as usize] {
-                        0x08
-                    } else {
-                        0
-                    })
-                }
-                Some(Selection::Buttons) => {
-                    (if !self.buttons[Button::A as usize] {
-                        0x01
-                    } else {
-                        0
-                    }) | (if !self.buttons[Button::B as usize] {
-                        0x02
-                    } else {
-                        0
-                    }) | (if !self.buttons[Button::Select as usize] {
-                        0x04
-                    } else {
-                        0
-                    }) | (if !self.buttons[Button::Start as usize] {
-                        0x08
-                    } else {
-                        0
-                    })
-                }
-                None => self.flag & 0x0f,
+    pub fn set_buttons(&mut self, mask: u8) {
+        for (i, &button) in BUTTONS.iter().enumerate() {
+            if mask & (1 << i) != 0 {
+                self.press(button);
+            } else {
+                self.release(button);
             }
+        }
+    }
+
+    pub fn select(&mut self, value: u8) {
+        let lines = self.lines();
+        self.select = value & (SELECT_DIRECTIONS | SELECT_BUTTONS);
+        self.check_falling(lines);
+    }
+
+    // The top two bits are unused and always read high.
+    pub fn value(&self) -> u8 {
+        0xc0 | self.select | self.lines()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn turbo_and_macros() {
+        let mut joypad = Joypad::new();
+        joypad.press_turbo(Button::A);
+        let pulses = (0..6)
+            .map(|_| {
+                joypad.frame();
+                joypad.buttons()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(pulses, [0x10, 0x10, 0, 0, 0x10, 0x10]);
+        joypad.release_turbo(Button::A);
+        assert_eq!(joypad.buttons(), 0);
+
+        joypad.press(Button::B);
+        joypad.play_macro(&"down*2 _ down+a".parse().unwrap());
+        let steps = (0..5)
+            .map(|_| {
+                joypad.frame();
+                joypad.buttons()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(steps, [0x22, 0x22, 0x20, 0x32, 0x20]);
+        assert!("a*x".parse::<Macro>().is_err());
+        assert!("jump".parse::<Macro>().is_err());
+    }
+
+    #[test]
+    fn reads_the_matrix() {
+        let mut joypad = Joypad::new();
+        joypad.press(Button::Left);
+        joypad.press(Button::A);
+        assert_eq!(joypad.value(), 0xff);
+        assert!(!joypad.check_interrupts());
+
+        joypad.select(0x20);
+        assert_eq!(joypad.value(), 0xed);
+        assert!(joypad.check_interrupts());
+        joypad.select(0x10);
+        assert_eq!(joypad.value(), 0xde);
+        joypad.select(0x00);
+        assert_eq!(joypad.value(), 0xcc);
+        assert!(joypad.check_interrupts());
+        joypad.press(Button::Start);
+        assert_eq!(joypad.value(), 0xc4);
+        assert!(joypad.check_interrupts());
+
+        joypad.press(Button::Right);
+        assert_eq!(joypad.buttons() & 0x0c, 0);
+        joypad.set_allow_opposing(true);
+        assert_eq!(joypad.buttons() & 0x0c, 0x0c);
     }
 }
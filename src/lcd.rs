@@ -1,11 +1,14 @@
 use crate::bytes;
 use crate::cart::GBType;
+use crate::state;
 use crate::{cpu::Interrupts, memory::Memory, timer::Timing};
 use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::{FromPrimitive, ToPrimitive};
+use serde::{Deserialize, Serialize};
 
 pub const SCREEN_SIZE: (u8, u8) = (160, 144);
 
+#[derive(Serialize, Deserialize)]
 pub struct LCD {
     regs: Registers,
     done_frame: bool,
@@ -19,12 +22,13 @@ pub struct LCD {
     video_bank: u8,
 
     oam_access: bool,
+    #[serde(with = "state::array")]
     oam: [u8; 0xa0],
 
     screen: Vec<u8>,
 }
 
-#[derive(PartialEq, Copy, Clone)]
+#[derive(PartialEq, Copy, Clone, Serialize, Deserialize)]
 enum Mode {
     HBlank,
     VBlank,
@@ -38,7 +42,7 @@ impl Default for Mode {
     }
 }
 
-#[derive(Default, Clone)]
+#[derive(Default, Clone, Serialize, Deserialize)]
 struct Registers {
     lcdc: LCDC,
     stat: STAT,
@@ -61,7 +65,7 @@ struct Registers {
     hdma_transfer: u8,
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 enum HDMA {
     None,
     GDMA,
@@ -74,7 +78,7 @@ impl Default for HDMA {
     }
 }
 
-#[derive(Default, Clone)]
+#[derive(Default, Clone, Serialize, Deserialize)]
 struct LCDC {
     display_enable: bool,
     window_tile_map_display_select: bool,
@@ -86,7 +90,7 @@ struct LCDC {
     bg_display: bool,
 }
 
-#[derive(PartialEq, Copy, Clone)]
+#[derive(PartialEq, Copy, Clone, Serialize, Deserialize)]
 enum SpriteSize {
     Small,
     Large,
@@ -98,7 +102,7 @@ impl Default for SpriteSize {
     }
 }
 
-#[derive(Default, Clone)]
+#[derive(Default, Clone, Serialize, Deserialize)]
 struct STAT {
     lyc_equals_lc: bool,
     mode_2_oam: bool,
@@ -682,12 +686,12 @@ trait Palette {
     fn color(&self, color: u8) -> Color;
 }
 
-#[derive(Default, Copy, Clone)]
+#[derive(Default, Copy, Clone, Serialize, Deserialize)]
 struct MonoPalette {
     color: [GrayShades; 4],
 }
 
-#[derive(FromPrimitive, ToPrimitive, Copy, Clone)]
+#[derive(FromPrimitive, ToPrimitive, Copy, Clone, Serialize, Deserialize)]
 enum GrayShades {
     White = 0x00,
     LightGray = 0x01,
@@ -1,20 +1,51 @@
+mod archive;
 mod bytes;
 mod cart;
+mod cheats;
+mod config;
 mod cpu;
+mod header;
 mod joypad;
 mod lcd;
 mod mbc;
 mod memory;
+mod movie;
+mod patch;
+mod rewind;
+mod save;
+mod script;
+mod search;
+mod speed;
+mod state;
 mod timer;
 mod ui;
+mod video;
 
-use clap::{App, Arg};
-use std::path::Path;
+use clap::{App, AppSettings, Arg, ArgGroup, ArgMatches, SubCommand};
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+const SEARCH_LIST_LIMIT: usize = 50;
+const DEFAULT_CONFIG: &str = "geebee.toml";
+const DEFAULT_SCALE: &str = "2";
+const DEFAULT_SCALING: &str = "integer";
+const DEFAULT_FAST_FORWARD: &str = "4";
+const DEFAULT_SLOW_MOTION: &str = "0.5";
+const DEFAULT_REWIND_INTERVAL: &str = "2";
+const DEFAULT_REWIND_BUDGET: &str = "64";
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<(), Box<dyn std::error::Error>> {
     let matches = App::new("geebee-rs")
         .version("0.1.0")
         .about("barebones gameboy emulator")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(
             Arg::with_name("rom")
                 .short("r")
@@ -30,6 +61,31 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .takes_value(true)
                 .help("path to the bootrom"),
         )
+        .arg(
+            Arg::with_name("force")
+                .short("f")
+                .long("force")
+                .takes_value(false)
+                .global(true)
+                .help("load roms with broken headers, warning instead of failing"),
+        )
+        .arg(
+            Arg::with_name("patch")
+                .short("p")
+                .long("patch")
+                .takes_value(true)
+                .global(true)
+                .help(
+                    "ips, ups or bps patch to apply to the rom, instead of one named like the rom",
+                ),
+        )
+        .arg(
+            Arg::with_name("save-dir")
+                .long("save-dir")
+                .takes_value(true)
+                .global(true)
+                .help("directory to keep battery saves in instead of next to the rom"),
+        )
         .arg(
             Arg::with_name("serial-stdout")
                 .short("s")
@@ -37,14 +93,234 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .takes_value(false)
                 .help("print out anything on the serial device into stdout"),
         )
+        .arg(
+            Arg::with_name("camera")
+                .short("c")
+                .long("camera")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("image fed to the pocket camera sensor, repeat to cycle through several"),
+        )
+        .arg(
+            Arg::with_name("cheats")
+                .long("cheats")
+                .takes_value(true)
+                .help(
+                    "cheat file to load, instead of one named like the rom with a .cht extension",
+                ),
+        )
+        .arg(
+            Arg::with_name("allow-opposing")
+                .long("allow-opposing")
+                .takes_value(false)
+                .help("allow pressing opposing directions of the d-pad at once"),
+        )
+        .arg(
+            Arg::with_name("record")
+                .long("record")
+                .takes_value(true)
+                .conflicts_with("play")
+                .help("record the joypad into a movie file"),
+        )
+        .arg(
+            Arg::with_name("play")
+                .long("play")
+                .takes_value(true)
+                .help("play back a movie file"),
+        )
+        .arg(
+            Arg::with_name("load-state")
+                .long("load-state")
+                .takes_value(true)
+                .help("start from a save state instead of power-on"),
+        )
+        .arg(
+            Arg::with_name("save-state")
+                .long("save-state")
+                .takes_value(true)
+                .help("write a save state when the emulator exits"),
+        )
+        .arg(
+            Arg::with_name("headless")
+                .long("headless")
+                .takes_value(false)
+                .help("run without a window, printing the hash of the final screen"),
+        )
+        .arg(
+            Arg::with_name("frames")
+                .long("frames")
+                .takes_value(true)
+                .help("number of frames to run headless, defaulting to the length of the movie"),
+        )
+        .arg(
+            Arg::with_name("input")
+                .long("input")
+                .takes_value(true)
+                .requires("headless")
+                .conflicts_with("play")
+                .help("script of joypad input for a headless run"),
+        )
+        .arg(
+            Arg::with_name("expect-hash")
+                .long("expect-hash")
+                .takes_value(true)
+                .help("fail unless the final screen of a headless run has this hash"),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .takes_value(true)
+                .help("key and gamepad bindings, defaulting to geebee.toml if it exists"),
+        )
+        .arg(
+            Arg::with_name("scale")
+                .long("scale")
+                .takes_value(true)
+                .default_value(DEFAULT_SCALE)
+                .help("initial window size in multiples of the screen size"),
+        )
+        .arg(
+            Arg::with_name("scaling")
+                .long("scaling")
+                .takes_value(true)
+                .possible_values(&["integer", "fit"])
+                .default_value(DEFAULT_SCALING)
+                .help("scale the screen by whole multiples only, or to fit the window"),
+        )
+        .arg(
+            Arg::with_name("fullscreen")
+                .long("fullscreen")
+                .takes_value(false)
+                .help("start in fullscreen"),
+        )
+        .arg(
+            Arg::with_name("fast-forward")
+                .long("fast-forward")
+                .takes_value(true)
+                .default_value(DEFAULT_FAST_FORWARD)
+                .help("speed while fast forwarding, 0 to run as fast as possible"),
+        )
+        .arg(
+            Arg::with_name("slow-motion")
+                .long("slow-motion")
+                .takes_value(true)
+                .default_value(DEFAULT_SLOW_MOTION)
+                .help("speed while in slow motion"),
+        )
+        .arg(
+            Arg::with_name("rewind-interval")
+                .long("rewind-interval")
+                .takes_value(true)
+                .default_value(DEFAULT_REWIND_INTERVAL)
+                .help("number of frames between rewind snapshots"),
+        )
+        .arg(
+            Arg::with_name("rewind-budget")
+                .long("rewind-budget")
+                .takes_value(true)
+                .default_value(DEFAULT_REWIND_BUDGET)
+                .help("megabytes of memory to keep rewind snapshots in, 0 to disable rewinding"),
+        )
+        .subcommand(
+            SubCommand::with_name("info")
+                .about("prints the cartridge header of a rom")
+                .arg(
+                    Arg::with_name("rom")
+                        .required(true)
+                        .help("path to the gameboy rom"),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .short("j")
+                        .long("json")
+                        .takes_value(false)
+                        .help("print the header as json"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("save")
+                .about("imports or exports the raw battery save of a rom")
+                .arg(
+                    Arg::with_name("rom")
+                        .required(true)
+                        .help("path to the gameboy rom"),
+                )
+                .arg(
+                    Arg::with_name("export")
+                        .long("export")
+                        .takes_value(true)
+                        .help("write the save to the given file"),
+                )
+                .arg(
+                    Arg::with_name("import")
+                        .long("import")
+                        .takes_value(true)
+                        .help("replace the save with the given file"),
+                )
+                .group(
+                    ArgGroup::with_name("action")
+                        .args(&["export", "import"])
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("search")
+                .about("searches the ram of a running rom for game variables, reading commands from stdin")
+                .arg(
+                    Arg::with_name("rom")
+                        .required(true)
+                        .help("path to the gameboy rom"),
+                )
+                .arg(
+                    Arg::with_name("word")
+                        .short("w")
+                        .long("word")
+                        .takes_value(false)
+                        .help("compare 16 bit values instead of bytes"),
+                )
+                .arg(
+                    Arg::with_name("signed")
+                        .long("signed")
+                        .takes_value(false)
+                        .help("treat values as signed"),
+                ),
+        )
         .get_matches();
 
-    let cart = cart::Cartridge::new().with_path(Path::new(matches.value_of("rom").unwrap()))?;
+    if let Some(matches) = matches.subcommand_matches("info") {
+        return info(matches);
+    }
+    if let Some(matches) = matches.subcommand_matches("save") {
+        return save(matches);
+    }
+    if let Some(matches) = matches.subcommand_matches("search") {
+        return search(matches);
+    }
 
-    let mut memory = memory::Memory::with_cartridge(cart);
+    let cart = cartridge(&matches)?;
+    let rom_crc = crc32fast::hash(cart.data());
+    let cheat_path = match matches.value_of("cheats") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => cart
+            .path()
+            .map(|path| path.with_extension("cht"))
+            .filter(|path| path.exists()),
+    };
+
+    let mut memory = memory::Memory::with_cartridge(cart)?;
+    if let Some(path) = cheat_path {
+        memory = memory.with_cheats(cheats::Cheats::new().with_path(&path)?);
+    }
     if let Some(bootrom) = matches.value_of("bootrom") {
         memory = memory.with_bootrom_path(Path::new(bootrom))?;
     }
+    if let Some(pictures) = matches.values_of("camera") {
+        memory = memory.with_camera_pictures(&pictures.map(Path::new).collect::<Vec<_>>())?;
+    }
+    if matches.is_present("play") {
+        memory = memory.without_save();
+    }
 
     let lcd = lcd::LCD::new(memory.gb());
     let mut cpu = cpu::CPU::new(memory, lcd);
@@ -52,8 +328,244 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     if matches.is_present("serial-stdout") {
         cpu.show_serial_output(true);
     }
+    cpu.joypad()
+        .set_allow_opposing(matches.is_present("allow-opposing"));
+    if let Some(path) = matches.value_of("load-state") {
+        cpu.load_state(&state::read_file(Path::new(path), rom_crc)?)?;
+    }
+    if let Some(path) = matches.value_of("play") {
+        let movie = movie::Movie::with_path(Path::new(path))?;
+        movie.check_rom(rom_crc)?;
+        println!("Playing {} frames from {:?}", movie.len(), movie.start());
+        cpu.load_state(movie.state())?;
+        cpu.set_movie(movie::Session::play(movie));
+    }
+    if matches.is_present("record") {
+        let start = if matches.is_present("load-state") {
+            movie::Start::SaveState
+        } else {
+            movie::Start::PowerOn
+        };
+        let movie = movie::Movie::new(rom_crc, start, cpu.save_state());
+        cpu.set_movie(movie::Session::record(movie));
+    }
+
+    let config = match matches.value_of("config") {
+        Some(path) => config::Config::with_path(Path::new(path))?,
+        None if Path::new(DEFAULT_CONFIG).exists() => {
+            config::Config::with_path(Path::new(DEFAULT_CONFIG))?
+        }
+        None => config::Config::default(),
+    };
+    cpu.joypad().set_turbo_rate(config.turbo_rate);
+
+    if matches.is_present("headless") {
+        let script = match matches.value_of("input") {
+            Some(path) => Some(script::Script::with_path(
+                Path::new(path),
+                &config.macros()?,
+            )?),
+            None => None,
+        };
+        headless(&mut cpu, script, &matches)?;
+    } else {
+        let interval = matches.value_of("rewind-interval").unwrap().parse()?;
+        let budget = matches
+            .value_of("rewind-budget")
+            .unwrap()
+            .parse::<usize>()?;
+        let fast_forward = matches.value_of("fast-forward").unwrap().parse::<f64>()?;
+        let speed = speed::Speed::new()
+            .with_fast_forward(Some(fast_forward).filter(|&speed| speed > 0.0))
+            .with_slow_motion(matches.value_of("slow-motion").unwrap().parse()?);
+        ui::launch(
+            &mut cpu,
+            rewind::Rewind::new(interval, budget << 20),
+            speed,
+            &config,
+            video::Video {
+                scale: matches.value_of("scale").unwrap().parse::<u32>()?.max(1),
+                scaling: matches.value_of("scaling").unwrap().parse()?,
+                fullscreen: matches.is_present("fullscreen"),
+            },
+        )?;
+    }
+
+    if let Some(path) = matches.value_of("record") {
+        let mut movie = cpu.take_movie().unwrap().into_movie();
+        movie.finish(cpu.lcd().screen());
+        movie.write(Path::new(path))?;
+    }
+    if let Some(path) = matches.value_of("save-state") {
+        state::write_file(Path::new(path), rom_crc, cpu.save_state())?;
+    }
+    Ok(())
+}
+
+// Runs a fixed number of frames as fast as possible, pressing buttons as the input script says,
+// then checks the final screen. Played back movies are checked against the screen they were
+// recorded with.
+fn headless(
+    cpu: &mut cpu::CPU,
+    mut script: Option<script::Script>,
+    matches: &ArgMatches,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (frames, expected) = match (matches.value_of("frames"), cpu.movie()) {
+        (Some(frames), _) => (frames.parse()?, None),
+        (None, Some(session)) if matches.is_present("play") => {
+            let movie = session.movie();
+            if movie.is_empty() {
+                return Err("movie has no frames".into());
+            }
+            (movie.len(), movie.screen_crc())
+        }
+        _ => return Err("running headless needs --frames or --play".into()),
+    };
 
-    ui::launch(cpu)?;
+    for frame in 0..frames {
+        if let Some(script) = script.as_mut() {
+            script.frame(frame, cpu.joypad());
+        }
+        cpu.cycle();
+    }
+    let hash = movie::screen_crc(cpu.lcd().screen());
+    println!("screen hash {:08x}", hash);
+
+    let expected = match matches.value_of("expect-hash") {
+        Some(hash) => Some(u32::from_str_radix(hash, 16)?),
+        None => expected,
+    };
+    match expected {
+        Some(expected) if expected != hash => {
+            Err(format!("screen hash mismatch, expected {:08x}", expected).into())
+        }
+        _ => Ok(()),
+    }
+}
 
+fn cartridge(matches: &ArgMatches) -> Result<cart::Cartridge, cart::Error> {
+    let mut cart = cart::Cartridge::new().with_lenient(matches.is_present("force"));
+    if let Some(dir) = matches.value_of("save-dir") {
+        cart = cart.with_save_dir(Path::new(dir));
+    }
+    if let Some(patch) = matches.value_of("patch") {
+        cart = cart.with_patch(Path::new(patch));
+    }
+    cart.with_path(Path::new(matches.value_of("rom").unwrap()))
+}
+
+fn save(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let cart = cartridge(matches)?;
+    let path = mbc::savepath(&cart).ok_or("cartridge has no battery backed save")?;
+    if let Some(export) = matches.value_of("export") {
+        let data = mbc::load_save(&cart)?;
+        if data.is_empty() {
+            return Err(format!("no save found at {}", path.display()).into());
+        }
+        std::fs::write(export, data)?;
+    }
+    if let Some(import) = matches.value_of("import") {
+        save::SaveFile::new(&path).write(&std::fs::read(import)?)?;
+    }
+    Ok(())
+}
+
+fn info(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let (_, data) = archive::read(Path::new(matches.value_of("rom").unwrap()))?;
+    let header = header::Header::parse(&data)?;
+    if matches.is_present("json") {
+        println!("{}", serde_json::to_string_pretty(&header)?);
+    } else {
+        println!("{}", header);
+    }
+    Ok(())
+}
+
+fn search(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let memory = memory::Memory::with_cartridge(cartridge(matches)?)?;
+    let lcd = lcd::LCD::new(memory.gb());
+    let mut cpu = cpu::CPU::new(memory, lcd);
+    let mut search = search::Search::new(cpu.memory())
+        .with_word(matches.is_present("word"))
+        .with_signed(matches.is_present("signed"));
+    println!("{} candidates", search.len());
+
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        if words.first() == Some(&"quit") {
+            break;
+        }
+        if let Err(e) = search_command(&mut cpu, &mut search, &words) {
+            eprintln!("error: {}", e);
+        }
+    }
+    Ok(())
+}
+
+// Commands either drive the emulator, or filter the candidates against the last snapshot.
+fn search_command(
+    cpu: &mut cpu::CPU,
+    search: &mut search::Search,
+    words: &[&str],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let filter = match words {
+        [] => return Ok(()),
+        ["frames"] | ["frames", _] => {
+            let frames = words.get(1).map_or(Ok(1), |n| n.parse::<usize>())?;
+            for _ in 0..frames {
+                cpu.cycle();
+            }
+            return Ok(());
+        }
+        ["press", button] => {
+            cpu.joypad().press(button.parse()?);
+            return Ok(());
+        }
+        ["release", button] => {
+            let button = button.parse()?;
+            cpu.joypad().release(button);
+            cpu.joypad().release_turbo(button);
+            return Ok(());
+        }
+        ["turbo", button] => {
+            cpu.joypad().press_turbo(button.parse()?);
+            return Ok(());
+        }
+        ["turbo-rate", frames] => {
+            cpu.joypad().set_turbo_rate(frames.parse()?);
+            return Ok(());
+        }
+        ["macro", steps @ ..] if !steps.is_empty() => {
+            cpu.joypad().play_macro(&steps.join(" ").parse()?);
+            return Ok(());
+        }
+        ["list"] => {
+            for candidate in search.candidates().take(SEARCH_LIST_LIMIT) {
+                println!("{}", candidate);
+            }
+            return Ok(());
+        }
+        ["reset"] => {
+            search.reset(cpu.memory());
+            println!("{} candidates", search.len());
+            return Ok(());
+        }
+        ["equal"] => search::Filter::Equal,
+        ["changed"] => search::Filter::Changed,
+        ["increased"] => search::Filter::Increased,
+        ["decreased"] => search::Filter::Decreased,
+        ["value", value] => search::Filter::Value(match value.strip_prefix("0x") {
+            Some(hex) => i32::from_str_radix(hex, 16)?,
+            None => value.parse()?,
+        }),
+        _ => return Err(format!("unknown command {}", words.join(" ")).into()),
+    };
+    search.filter(cpu.memory(), filter);
+    if search.is_empty() {
+        println!("no candidates left, reset to start over");
+    } else {
+        println!("{} candidates", search.len());
+    }
     Ok(())
 }
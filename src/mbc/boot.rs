@@ -1,4 +1,6 @@
 use crate::mbc::MBC;
+use crate::state;
+use crate::timer::Timing;
 
 pub struct Boot {
     rom: Vec<u8>,
@@ -35,4 +37,58 @@ impl MBC for Boot {
     }
 
     fn write(&mut self, _address: u16, _value: u8) {}
+
+    fn advance(&mut self, timing: Timing) {
+        if let Some(mbc) = self.mbc.as_mut() {
+            mbc.advance(timing);
+        }
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        self.mbc.as_ref().and_then(|mbc| mbc.save_data())
+    }
+
+    fn save_changed(&mut self) -> bool {
+        self.mbc.as_mut().is_some_and(|mbc| mbc.save_changed())
+    }
+
+    fn ram(&self) -> &[u8] {
+        self.mbc.as_ref().map_or(&[], |mbc| mbc.ram())
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        match self.mbc.as_mut() {
+            Some(mbc) => mbc.ram_mut(),
+            None => &mut [],
+        }
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        self.mbc
+            .as_ref()
+            .map_or_else(Vec::new, |mbc| mbc.save_state())
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), state::Error> {
+        match self.mbc.as_mut() {
+            Some(mbc) => mbc.load_state(state),
+            None => Ok(()),
+        }
+    }
+
+    fn rumble(&self) -> bool {
+        self.mbc.as_ref().is_some_and(|mbc| mbc.rumble())
+    }
+
+    fn tilt(&mut self, x: f32, y: f32) {
+        if let Some(mbc) = self.mbc.as_mut() {
+            mbc.tilt(x, y);
+        }
+    }
+
+    fn pictures(&mut self, pictures: Vec<Vec<u8>>) {
+        if let Some(mbc) = self.mbc.as_mut() {
+            mbc.pictures(pictures);
+        }
+    }
 }
@@ -0,0 +1,268 @@
+use crate::cart;
+use crate::mbc::{load_state, prepare_save, ram_address, read_rom, save_state, MBC};
+use crate::state;
+use crate::timer::Timing;
+use image::imageops::FilterType;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+pub const SENSOR_SIZE: (usize, usize) = (128, 112);
+const REGISTERS: usize = 0x36;
+
+#[derive(Serialize, Deserialize)]
+pub struct Camera {
+    #[serde(skip)]
+    cart: cart::Cartridge,
+    rom_bank: usize,
+
+    ram_enabled: bool,
+    ram_bank: usize,
+    ram: Vec<u8>,
+
+    // Setting bit 4 of the ram bank register maps the sensor registers instead of the ram.
+    registers_selected: bool,
+    #[serde(with = "state::array")]
+    registers: [u8; REGISTERS],
+    capture_cycles: u32,
+
+    // Grayscale frames fed to the sensor, cycling through them with every capture.
+    #[serde(skip)]
+    pictures: Vec<Vec<u8>>,
+    captures: usize,
+}
+
+impl Camera {
+    pub fn new(cart: cart::Cartridge) -> Result<Self, cart::Error> {
+        let ram = prepare_save(&cart, cart.ram_size())?;
+        Ok(Self {
+            cart,
+            rom_bank: 1,
+
+            ram_enabled: false,
+            ram_bank: 0,
+            ram,
+
+            registers_selected: false,
+            registers: [0; REGISTERS],
+            capture_cycles: 0,
+
+            pictures: Vec::new(),
+            captures: 0,
+        })
+    }
+
+    fn start_capture(&mut self) {
+        let exposure = ((self.registers[2] as u32) << 8) | self.registers[3] as u32;
+        let n = self.registers[1] & 0x80 != 0;
+        // The sensor runs on a 1 MiHz clock, a quarter of the cpu clock the mapper advances by.
+        self.capture_cycles = 4 * (32446 + if n { 0 } else { 512 } + exposure * 16);
+    }
+
+    fn sensor(&self) -> Vec<u8> {
+        match self.pictures.len() {
+            0 => vec![0x80; SENSOR_SIZE.0 * SENSOR_SIZE.1],
+            len => self.pictures[self.captures % len].clone(),
+        }
+    }
+
+    // Runs the sensor image through the exposure, edge enhancement and inversion settings, then
+    // dithers it to 2 bit tiles at 0x0100 of the first ram bank.
+    fn capture(&mut self) {
+        let (width, height) = SENSOR_SIZE;
+        let exposure = ((self.registers[2] as u32) << 8) | self.registers[3] as u32;
+        let pixels = self
+            .sensor()
+            .iter()
+            .map(|&p| p as f32 * exposure as f32 / 0x1000 as f32)
+            .collect::<Vec<f32>>();
+        let pixel = |x: isize, y: isize| {
+            let x = x.max(0).min(width as isize - 1) as usize;
+            let y = y.max(0).min(height as isize - 1) as usize;
+            pixels[y * width + x]
+        };
+
+        const EDGE_RATIOS: [f32; 8] = [0.5, 0.75, 1.0, 1.25, 2.0, 3.0, 4.0, 5.0];
+        let edge_ratio = EDGE_RATIOS[(self.registers[4] as usize >> 4) & 0x07];
+        let edge_enhance = self.registers[1] & 0x60 == 0x60;
+        let invert = self.registers[4] & 0x08 != 0;
+
+        for y in 0..height {
+            for x in 0..width {
+                let (sx, sy) = (x as isize, y as isize);
+                let mut value = pixel(sx, sy);
+                if edge_enhance {
+                    let around = pixel(sx - 1, sy)
+                        + pixel(sx + 1, sy)
+                        + pixel(sx, sy - 1)
+                        + pixel(sx, sy + 1);
+                    value += (4.0 * value - around) * edge_ratio / 4.0;
+                }
+                let mut value = value.clamp(0.0, 255.0) as u8;
+                if invert {
+                    value = 255 - value;
+                }
+
+                let matrix = 6 + ((y & 3) * 4 + (x & 3)) * 3;
+                let color = match value {
+                    v if v < self.registers[matrix] => 3,
+                    v if v < self.registers[matrix + 1] => 2,
+                    v if v < self.registers[matrix + 2] => 1,
+                    _ => 0,
+                };
+
+                let tile = (y / 8) * (width / 8) + x / 8;
+                let offset = 0x0100 + tile * 16 + (y % 8) * 2;
+                let bit = 0x80 >> (x % 8);
+                if offset + 1 >= self.ram.len() {
+                    continue;
+                }
+                self.ram[offset] = (self.ram[offset] & !bit) | if color & 1 != 0 { bit } else { 0 };
+                self.ram[offset + 1] =
+                    (self.ram[offset + 1] & !bit) | if color & 2 != 0 { bit } else { 0 };
+            }
+        }
+        self.captures += 1;
+    }
+}
+
+impl MBC for Camera {
+    fn read(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3fff => read_rom(&self.cart, 0, address),
+            0x4000..=0x7fff => read_rom(&self.cart, self.rom_bank, address),
+            // Only the capture register can be read back, reporting whether a capture is running.
+            0xa000..=0xbfff if self.registers_selected => {
+                if address & 0x7f == 0 {
+                    (self.registers[0] & 0x06) | if self.capture_cycles > 0 { 1 } else { 0 }
+                } else {
+                    0x00
+                }
+            }
+            // The ram stays readable even while it is write protected.
+            0xa000..=0xbfff => match ram_address(&self.ram, self.ram_bank, address) {
+                Some(address) => self.ram[address],
+                None => 0xff,
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1fff => self.ram_enabled = (value & 0x0f) == 0x0a,
+            // Unlike most controllers, bank 0 can also be mapped to the switchable area.
+            0x2000..=0x3fff => self.rom_bank = (value & 0x3f) as usize,
+            0x4000..=0x5fff => {
+                self.registers_selected = value & 0x10 != 0;
+                if !self.registers_selected {
+                    self.ram_bank = (value & 0x0f) as usize;
+                }
+            }
+            0x6000..=0x7fff => {}
+            0xa000..=0xbfff if self.registers_selected => {
+                let register = (address & 0x7f) as usize;
+                if register >= REGISTERS {
+                    return;
+                }
+                self.registers[register] = value;
+                if register == 0 && value & 0x01 != 0 && self.capture_cycles == 0 {
+                    self.start_capture();
+                }
+            }
+            0xa000..=0xbfff => {
+                if !self.ram_enabled || self.capture_cycles > 0 {
+                    return;
+                }
+                if let Some(address) = ram_address(&self.ram, self.ram_bank, address) {
+                    self.ram[address] = value;
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn advance(&mut self, timing: Timing) {
+        if self.capture_cycles == 0 {
+            return;
+        }
+        self.capture_cycles = self.capture_cycles.saturating_sub(timing as u32);
+        if self.capture_cycles == 0 {
+            self.capture();
+        }
+    }
+
+    fn pictures(&mut self, pictures: Vec<Vec<u8>>) {
+        self.pictures = pictures;
+        self.captures = 0;
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        Some(self.ram.clone())
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        save_state(self)
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), state::Error> {
+        // The pictures fed to the sensor are not part of the state, so they are kept.
+        let pictures = std::mem::take(&mut self.pictures);
+        let result = load_state(self, state, |mbc| &mut mbc.cart);
+        self.pictures = pictures;
+        result
+    }
+}
+
+// Loads an image as a grayscale sensor frame, scaling and cropping it to fill the sensor.
+pub fn load_picture(path: &Path) -> Result<Vec<u8>, image::ImageError> {
+    let (width, height) = (SENSOR_SIZE.0 as u32, SENSOR_SIZE.1 as u32);
+    Ok(image::open(path)?
+        .resize_to_fill(width, height, FilterType::Triangle)
+        .to_luma8()
+        .into_raw())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mbc::test::rom;
+
+    #[test]
+    fn capture() {
+        let mut mbc = Camera::new(rom(0xfc, 4, 0x04)).unwrap();
+        let mut picture = vec![0x00; SENSOR_SIZE.0 * SENSOR_SIZE.1];
+        picture[1] = 0xff;
+        mbc.pictures(vec![picture]);
+
+        mbc.write(0x4000, 0x10);
+        mbc.write(0xa002, 0x10);
+        mbc.write(0xa003, 0x00);
+        for register in (0xa006..0xa036).step_by(3) {
+            mbc.write(register, 0x40);
+            mbc.write(register + 1, 0x80);
+            mbc.write(register + 2, 0xc0);
+        }
+        mbc.write(0xa000, 0x01);
+        assert_eq!(mbc.read(0xa000), 0x01);
+        let mut cycles = 0;
+        while mbc.read(0xa000) & 0x01 != 0 {
+            mbc.advance(0x100);
+            cycles += 0x100;
+        }
+        // Four cpu cycles for each of the 32446 + 512 + 16 * exposure sensor clocks, rounded up.
+        assert_eq!(cycles, 393_984);
+
+        mbc.write(0x4000, 0x00);
+        assert_eq!(mbc.read(0xa100), 0b1011_1111);
+        assert_eq!(mbc.read(0xa101), 0b1011_1111);
+        assert_eq!(mbc.read(0xa000), 0x00);
+    }
+}
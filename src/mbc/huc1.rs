@@ -0,0 +1,116 @@
+use crate::cart;
+use crate::mbc::{load_state, prepare_save, ram_address, read_rom, save_state, MBC};
+use crate::state;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+pub struct HuC1 {
+    #[serde(skip)]
+    cart: cart::Cartridge,
+    rom_bank: usize,
+
+    // Writing 0x0e to the enable register maps the infrared port instead of the ram.
+    ir_mode: bool,
+    ram_bank: usize,
+    ram: Vec<u8>,
+}
+
+impl HuC1 {
+    pub fn new(cart: cart::Cartridge) -> Result<Self, cart::Error> {
+        let ram = prepare_save(&cart, cart.ram_size())?;
+        Ok(Self {
+            cart,
+            rom_bank: 1,
+
+            ir_mode: false,
+            ram_bank: 0,
+            ram,
+        })
+    }
+}
+
+impl MBC for HuC1 {
+    fn read(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3fff => read_rom(&self.cart, 0, address),
+            0x4000..=0x7fff => read_rom(&self.cart, self.rom_bank, address),
+            // Without a link partner no light is ever received.
+            0xa000..=0xbfff if self.ir_mode => 0xc0,
+            0xa000..=0xbfff => match ram_address(&self.ram, self.ram_bank, address) {
+                Some(address) => self.ram[address],
+                None => 0xff,
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1fff => self.ir_mode = (value & 0x0f) == 0x0e,
+            0x2000..=0x3fff => {
+                self.rom_bank = match value & 0x3f {
+                    0x00 => 0x01,
+                    bank => bank as usize,
+                }
+            }
+            0x4000..=0x5fff => self.ram_bank = (value & 0x03) as usize,
+            0x6000..=0x7fff => {}
+            0xa000..=0xbfff if self.ir_mode => {}
+            0xa000..=0xbfff => {
+                if let Some(address) = ram_address(&self.ram, self.ram_bank, address) {
+                    self.ram[address] = value;
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        Some(self.ram.clone())
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        save_state(self)
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), state::Error> {
+        load_state(self, state, |mbc| &mut mbc.cart)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mbc::test::rom;
+
+    #[test]
+    fn banking_and_infrared() {
+        let mut mbc = HuC1::new(rom(0xff, 8, 0x03)).unwrap();
+        assert_eq!(mbc.read(0x4000), 0x01);
+        mbc.write(0x2000, 0x05);
+        assert_eq!(mbc.read(0x4000), 0x05);
+        mbc.write(0x2000, 0x00);
+        assert_eq!(mbc.read(0x4000), 0x01);
+
+        mbc.write(0x4000, 0x02);
+        mbc.write(0xa000, 0x42);
+        mbc.write(0x4000, 0x00);
+        assert_eq!(mbc.read(0xa000), 0x00);
+        mbc.write(0x4000, 0x02);
+        assert_eq!(mbc.read(0xa000), 0x42);
+
+        mbc.write(0x0000, 0x0e);
+        assert_eq!(mbc.read(0xa000), 0xc0);
+        mbc.write(0xa000, 0x01);
+        mbc.write(0x0000, 0x0a);
+        assert_eq!(mbc.read(0xa000), 0x42);
+    }
+}
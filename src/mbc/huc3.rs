@@ -0,0 +1,255 @@
+use crate::cart;
+use crate::mbc::{load_save, load_state, ram_address, read_rom, save_state, unix_time, MBC};
+use crate::state;
+use crate::timer::Timing;
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+
+const CYCLES_PER_MINUTE: u32 = 4_194_304 * 60;
+const CLOCK_SAVE_SIZE: usize = 16;
+
+#[derive(Serialize, Deserialize)]
+pub struct HuC3 {
+    #[serde(skip)]
+    cart: cart::Cartridge,
+    rom_bank: usize,
+
+    mode: u8,
+    ram_bank: usize,
+    ram: Vec<u8>,
+
+    clock: Clock,
+}
+
+impl HuC3 {
+    pub fn new(cart: cart::Cartridge) -> Result<Self, cart::Error> {
+        let ram_size = cart.ram_size();
+        let mut ram = load_save(&cart)?;
+        let clock = Clock::with_save(ram.get(ram_size..).unwrap_or(&[]));
+        ram.resize(ram_size, 0);
+        Ok(Self {
+            cart,
+            rom_bank: 1,
+
+            mode: 0,
+            ram_bank: 0,
+            ram,
+
+            clock,
+        })
+    }
+}
+
+impl MBC for HuC3 {
+    fn read(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3fff => read_rom(&self.cart, 0, address),
+            0x4000..=0x7fff => read_rom(&self.cart, self.rom_bank, address),
+            0xa000..=0xbfff => match self.mode {
+                0x00 | 0x0a => match ram_address(&self.ram, self.ram_bank, address) {
+                    Some(address) => self.ram[address],
+                    None => 0xff,
+                },
+                0x0c => self.clock.response(),
+                // The clock is always ready to accept commands.
+                0x0d => 0xff,
+                // Without a link partner no light is ever received.
+                0x0e => 0xc0,
+                _ => 0xff,
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1fff => self.mode = value & 0x0f,
+            0x2000..=0x3fff => self.rom_bank = (value & 0x7f) as usize,
+            0x4000..=0x5fff => self.ram_bank = (value & 0x03) as usize,
+            0x6000..=0x7fff => {}
+            0xa000..=0xbfff => match self.mode {
+                0x0a => {
+                    if let Some(address) = ram_address(&self.ram, self.ram_bank, address) {
+                        self.ram[address] = value;
+                    }
+                }
+                0x0b => self.clock.command(value),
+                _ => {}
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    fn advance(&mut self, timing: Timing) {
+        self.clock.advance(timing);
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        Some([&self.ram[..], &self.clock.save()].concat())
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        save_state(self)
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), state::Error> {
+        load_state(self, state, |mbc| &mut mbc.cart)
+    }
+}
+
+// The HuC3 clock counts minutes of the day and days, and is accessed through a small nibble
+// addressed memory using 4 bit commands.
+#[derive(Serialize, Deserialize)]
+struct Clock {
+    minutes: u16,
+    days: u16,
+    cycles: u32,
+
+    #[serde(with = "state::array")]
+    memory: [u8; 0x80],
+    address: u8,
+    command: u8,
+    response: u8,
+}
+
+impl Clock {
+    fn new() -> Self {
+        Self {
+            minutes: 0,
+            days: 0,
+            cycles: 0,
+            memory: [0; 0x80],
+            address: 0,
+            command: 0,
+            response: 0,
+        }
+    }
+
+    fn with_save(data: &[u8]) -> Self {
+        let mut clock = Self::new();
+        if data.len() < CLOCK_SAVE_SIZE {
+            return clock;
+        }
+        let word = |i: usize| u32::from_le_bytes(data[i..i + 4].try_into().unwrap());
+        clock.minutes = (word(0) % (24 * 60)) as u16;
+        clock.days = (word(4) & 0x0fff) as u16;
+        let saved = u64::from_le_bytes(data[8..16].try_into().unwrap());
+        clock.advance_minutes(unix_time().saturating_sub(saved) / 60);
+        clock
+    }
+
+    fn save(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(CLOCK_SAVE_SIZE);
+        data.extend_from_slice(&(self.minutes as u32).to_le_bytes());
+        data.extend_from_slice(&(self.days as u32).to_le_bytes());
+        data.extend_from_slice(&unix_time().to_le_bytes());
+        data
+    }
+
+    fn advance(&mut self, timing: Timing) {
+        self.cycles += timing as u32;
+        while self.cycles >= CYCLES_PER_MINUTE {
+            self.cycles -= CYCLES_PER_MINUTE;
+            self.tick();
+        }
+    }
+
+    fn tick(&mut self) {
+        self.advance_minutes(1);
+    }
+
+    fn advance_minutes(&mut self, minutes: u64) {
+        let minutes = self.minutes as u64 + minutes;
+        self.minutes = (minutes % (24 * 60)) as u16;
+        self.days = ((self.days as u64 + minutes / (24 * 60)) & 0x0fff) as u16;
+    }
+
+    fn response(&self) -> u8 {
+        0x80 | (self.command & 0x70) | (self.response & 0x0f)
+    }
+
+    fn command(&mut self, value: u8) {
+        self.command = value;
+        let argument = value & 0x0f;
+        match value & 0x70 {
+            0x10 => {
+                self.response = self.memory[self.address as usize];
+                self.address = (self.address + 1) & 0x7f;
+            }
+            0x30 => {
+                self.memory[self.address as usize] = argument;
+                self.address = (self.address + 1) & 0x7f;
+            }
+            0x40 => self.address = (self.address & 0x70) | argument,
+            0x50 => self.address = (self.address & 0x0f) | ((argument & 0x07) << 4),
+            0x60 => match argument {
+                0x00 => {
+                    for i in 0..3 {
+                        self.memory[i] = (self.minutes >> (i * 4)) as u8 & 0x0f;
+                        self.memory[i + 3] = (self.days >> (i * 4)) as u8 & 0x0f;
+                    }
+                }
+                0x01 => {
+                    self.minutes = 0;
+                    self.days = 0;
+                    for i in 0..3 {
+                        self.minutes |= (self.memory[i] as u16) << (i * 4);
+                        self.days |= (self.memory[i + 3] as u16) << (i * 4);
+                    }
+                    self.minutes %= 24 * 60;
+                    self.cycles = 0;
+                }
+                0x02 => self.response = 0x01,
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mbc::test::rom;
+
+    #[test]
+    fn clock_commands() {
+        let mut mbc = HuC3::new(rom(0xfe, 4, 0x03)).unwrap();
+        for _ in 0..(61 * 60 * 64) {
+            mbc.advance(0x8000);
+            mbc.advance(0x8000);
+        }
+        mbc.write(0x0000, 0x0b);
+        mbc.write(0xa000, 0x60);
+        mbc.write(0xa000, 0x40);
+        mbc.write(0xa000, 0x50);
+        mbc.write(0xa000, 0x10);
+        mbc.write(0x0000, 0x0c);
+        assert_eq!(mbc.read(0xa000), 0x90 | (61 & 0x0f));
+        mbc.write(0x0000, 0x0b);
+        mbc.write(0xa000, 0x10);
+        mbc.write(0x0000, 0x0c);
+        assert_eq!(mbc.read(0xa000), 0x90 | (61 >> 4));
+    }
+
+    #[test]
+    fn clock_catches_up() {
+        let mut data = [0xff; CLOCK_SAVE_SIZE];
+        data[8..16].copy_from_slice(&0u64.to_le_bytes());
+        let clock = Clock::with_save(&data);
+        assert!(clock.minutes < 24 * 60);
+        assert!(clock.days <= 0x0fff);
+
+        let mut clock = Clock::new();
+        clock.advance_minutes(3 * 24 * 60 + 5);
+        assert_eq!((clock.minutes, clock.days), (5, 3));
+    }
+}
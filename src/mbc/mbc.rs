@@ -1,42 +1,210 @@
 use crate::cart;
-use std::fs::File;
+use crate::state;
+use crate::timer::Timing;
+use serde::{de::DeserializeOwned, Serialize};
+use std::fs::{self, File};
+use std::io::{self, Read};
 use std::path::{Path, PathBuf};
-use std::{io, io::Read, io::Write};
 
 pub trait MBC {
     fn read(&self, address: u16) -> u8;
     fn write(&mut self, address: u16, value: u8);
+    fn advance(&mut self, _timing: Timing) {}
+    fn rumble(&self) -> bool {
+        false
+    }
+    fn tilt(&mut self, _x: f32, _y: f32) {}
+    fn pictures(&mut self, _pictures: Vec<Vec<u8>>) {}
+    // The battery backed state to persist, if the mapper has any.
+    fn save_data(&self) -> Option<Vec<u8>> {
+        None
+    }
+    // Whether battery backed state outside the ram area changed since the last call, such as
+    // flash programmed through the rom area.
+    fn save_changed(&mut self) -> bool {
+        false
+    }
+    // The cartridge ram across all banks, for tools that inspect it and cheats that poke it.
+    fn ram(&self) -> &[u8] {
+        &[]
+    }
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut []
+    }
+    // Banking registers, ram and timers, for capturing and restoring the machine state.
+    fn save_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+    fn load_state(&mut self, _state: &[u8]) -> Result<(), state::Error> {
+        Ok(())
+    }
+}
+
+pub fn save_state<T: Serialize>(mbc: &T) -> Vec<u8> {
+    state::to_bytes(mbc)
+}
+
+// Restores a mapper from its captured state, keeping the cartridge it was created with.
+pub fn load_state<T: DeserializeOwned>(
+    mbc: &mut T,
+    data: &[u8],
+    cart: fn(&mut T) -> &mut cart::Cartridge,
+) -> Result<(), state::Error> {
+    let mut loaded: T = state::from_bytes(data)?;
+    std::mem::swap(cart(&mut loaded), cart(mbc));
+    *mbc = loaded;
+    Ok(())
 }
 
-pub fn prepare_save(cart: &cart::Cartridge, size: usize) -> Result<Vec<u8>, io::Error> {
-    let mut data = vec![0; size];
-    if !can_handle_savefiles(cart) {
-        return Ok(data);
+// Banks beyond the end of the rom or ram wrap around, as the unused high bits of the bank
+// number are simply not connected on the cartridge.
+pub fn read_rom(cart: &cart::Cartridge, bank: usize, address: u16) -> u8 {
+    let data = cart.data();
+    let banks = (data.len() / 0x4000).max(1);
+    data[(bank % banks) * 0x4000 + (address as usize & 0x3fff)]
+}
+
+pub fn ram_address(ram: &[u8], bank: usize, address: u16) -> Option<usize> {
+    if ram.is_empty() {
+        return None;
     }
-    let p = savepath(cart.path().unwrap());
-    match File::open(&p) {
-        Ok(mut f) => {
-            f.read_to_end(&mut data)?;
+    Some((bank * 0x2000 + (address as usize & 0x1fff)) % ram.len())
+}
+
+pub fn prepare_save(cart: &cart::Cartridge, size: usize) -> Result<Vec<u8>, cart::Error> {
+    let mut data = load_save(cart)?;
+    data.resize(size, 0);
+    Ok(data)
+}
+
+pub fn load_save(cart: &cart::Cartridge) -> Result<Vec<u8>, cart::Error> {
+    let mut data = Vec::new();
+    let p = match savepath(cart) {
+        Some(p) => p,
+        None => return Ok(data),
+    };
+    // A failed migration falls back to reading the legacy save where it is, so the game still
+    // starts with its progress.
+    let p = match migrate_save(cart, &p) {
+        Ok(()) => p,
+        Err(e) => {
+            eprintln!("warning: unable to migrate save: {}", e);
+            legacy_savepath(cart)
         }
-        Err(_) => {}
     };
+    if let Ok(mut f) = File::open(&p) {
+        f.read_to_end(&mut data)
+            .map_err(|source| cart::Error::Save { source })?;
+    }
     Ok(data)
 }
 
-pub fn handle_save(cart: &cart::Cartridge, ram: &[u8]) -> Result<(), io::Error> {
-    if !can_handle_savefiles(cart) {
+// Only cartridges with a battery loaded from a file keep their saves. They are named after the
+// rom and stored next to it, unless a saves directory is configured.
+pub fn savepath(cart: &cart::Cartridge) -> Option<PathBuf> {
+    if !cart.cart_type().battery {
+        return None;
+    }
+    let path = cart.path()?.with_extension("sav");
+    match cart.save_dir() {
+        Some(dir) => Some(dir.join(path.file_name()?)),
+        None => Some(path),
+    }
+}
+
+// Older versions stored saves as `<rom>.gb.save`, which are copied over to the new location
+// the first time the rom is loaded. The original is kept, as the rom might be on a read-only
+// share.
+fn migrate_save(cart: &cart::Cartridge, path: &Path) -> Result<(), io::Error> {
+    let legacy = legacy_savepath(cart);
+    if path.exists() || !legacy.exists() {
         return Ok(());
     }
-    let p = savepath(cart.path().unwrap());
-    let mut f = File::create(p)?;
-    f.write_all(ram)?;
+    eprintln!("migrating save {} to {}", legacy.display(), path.display());
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::copy(&legacy, path)?;
     Ok(())
 }
 
-fn can_handle_savefiles(cart: &cart::Cartridge) -> bool {
-    cart.cart_type().battery && cart.path().is_some()
+fn legacy_savepath(cart: &cart::Cartridge) -> PathBuf {
+    cart.path().unwrap().with_extension("gb.save")
 }
 
-fn savepath(path: &Path) -> PathBuf {
-    path.with_extension(Path::new("gb.save"))
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::mbc::{MBC1, MBC5};
+
+    // Builds a rom with the given cartridge type where each bank starts with its own number.
+    pub fn rom(cart_type: u8, banks: usize, ram_size: u8) -> cart::Cartridge {
+        let mut data = vec![0; banks * 0x4000];
+        for bank in 0..banks {
+            data[bank * 0x4000] = bank as u8;
+        }
+        data[0x0147] = cart_type;
+        data[0x0148] = (banks / 2).trailing_zeros() as u8;
+        data[0x0149] = ram_size;
+        cart::Cartridge::new()
+            .with_lenient(true)
+            .with_data(&data)
+            .unwrap()
+    }
+
+    #[test]
+    fn migrates_legacy_saves() {
+        let dir = std::env::temp_dir().join(format!("geebee-migrate-{}", std::process::id()));
+        let saves = dir.join("saves");
+        fs::create_dir_all(&dir).unwrap();
+        let mut data = vec![0; 0x8000];
+        data[0x0147] = 0x03;
+        data[0x0149] = 0x02;
+        fs::write(dir.join("game.gb"), &data).unwrap();
+        fs::write(dir.join("game.gb.save"), [0x42]).unwrap();
+
+        let cart = cart::Cartridge::new()
+            .with_lenient(true)
+            .with_save_dir(&saves)
+            .with_path(&dir.join("game.gb"))
+            .unwrap();
+        assert_eq!(savepath(&cart), Some(saves.join("game.sav")));
+        assert_eq!(prepare_save(&cart, 2).unwrap(), [0x42, 0x00]);
+        assert!(saves.join("game.sav").exists());
+        assert!(dir.join("game.gb.save").exists());
+
+        // A saves directory that cannot be created still loads the legacy save.
+        fs::write(dir.join("blocked"), []).unwrap();
+        let cart = cart::Cartridge::new()
+            .with_lenient(true)
+            .with_save_dir(&dir.join("blocked"))
+            .with_path(&dir.join("game.gb"))
+            .unwrap();
+        assert_eq!(prepare_save(&cart, 2).unwrap(), [0x42, 0x00]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rom_banks_wrap() {
+        let mut mbc = MBC5::new(rom(0x19, 4, 0)).unwrap();
+        mbc.write(0x2000, 0x06);
+        assert_eq!(mbc.read(0x4000), 0x02);
+        mbc.write(0x3000, 0x01);
+        assert_eq!(mbc.read(0x4000), 0x02);
+    }
+
+    #[test]
+    fn ram_banks_wrap_and_absent_ram_is_open_bus() {
+        let mut mbc = MBC1::new(rom(0x03, 4, 0x02)).unwrap();
+        mbc.write(0x0000, 0x0a);
+        mbc.write(0x6000, 0x01);
+        mbc.write(0x4000, 0x03);
+        mbc.write(0xa000, 0x42);
+        mbc.write(0x4000, 0x00);
+        assert_eq!(mbc.read(0xa000), 0x42);
+
+        let mbc = MBC1::new(rom(0x01, 4, 0x00)).unwrap();
+        assert_eq!(mbc.read(0xa000), 0xff);
+    }
 }
@@ -1,31 +1,83 @@
 use crate::cart;
-use crate::mbc::{handle_save, prepare_save, MBC};
+use crate::header::NINTENDO_LOGO;
+use crate::mbc::{load_state, prepare_save, ram_address, read_rom, save_state, MBC};
+use crate::state;
+use serde::{Deserialize, Serialize};
 
+#[derive(Serialize, Deserialize)]
 pub struct MBC1 {
+    #[serde(skip)]
     cart: cart::Cartridge,
-    rom_bank: usize,
+    // Multicarts wire only 4 bits of the lower bank register, so the upper register selects
+    // one of the 256 KiB games on the cartridge.
+    multicart: bool,
 
-    rom_ram_mode: u8,
+    lower_bank: u8,
+    upper_bank: u8,
+    advanced_banking: bool,
     ram_enabled: bool,
-    ram_bank: u8,
     ram: Vec<u8>,
 }
 
 impl MBC1 {
-    pub fn new(cart: cart::Cartridge) -> Self {
-        let ram_size = match cart.ram_size() {
-            0 => 0,
-            s => 0x800 << s,
-        };
-        let ram = prepare_save(&cart, ram_size).unwrap();
-        Self {
+    pub fn new(cart: cart::Cartridge) -> Result<Self, cart::Error> {
+        let ram = prepare_save(&cart, cart.ram_size())?;
+        Ok(Self {
+            multicart: Self::is_multicart(&cart),
             cart,
-            rom_bank: 1,
 
-            rom_ram_mode: 0,
+            lower_bank: 1,
+            upper_bank: 0,
+            advanced_banking: false,
             ram_enabled: false,
-            ram_bank: 0,
             ram,
+        })
+    }
+
+    // MBC1M compilation cartridges are 1 MiB and repeat the boot logo at the start of every
+    // game they contain.
+    fn is_multicart(cart: &cart::Cartridge) -> bool {
+        let data = cart.data();
+        if data.len() != 0x100000 {
+            return false;
+        }
+        let logos = (0..4)
+            .map(|game| game * 0x40000 + 0x0104)
+            .filter(|offset| data[*offset..*offset + NINTENDO_LOGO.len()] == NINTENDO_LOGO)
+            .count();
+        logos > 1
+    }
+
+    fn upper_shift(&self) -> u8 {
+        if self.multicart {
+            4
+        } else {
+            5
+        }
+    }
+
+    fn low_rom_bank(&self) -> usize {
+        if self.advanced_banking {
+            (self.upper_bank << self.upper_shift()) as usize
+        } else {
+            0
+        }
+    }
+
+    fn high_rom_bank(&self) -> usize {
+        let lower = if self.multicart {
+            self.lower_bank & 0x0f
+        } else {
+            self.lower_bank
+        };
+        ((self.upper_bank << self.upper_shift()) | lower) as usize
+    }
+
+    fn ram_bank(&self) -> usize {
+        if self.advanced_banking {
+            self.upper_bank as usize
+        } else {
+            0
         }
     }
 }
@@ -33,61 +85,102 @@ impl MBC1 {
 impl MBC for MBC1 {
     fn read(&self, address: u16) -> u8 {
         match address {
-            0x0000..=0x3fff => self.cart.data()[address as usize],
-            0x4000..=0x7fff => {
-                let address = (0x4000 * (self.rom_bank)) + (address as usize - 0x4000);
-                self.cart.data()[address]
-            }
-            0xa000..=0xbfff => {
-                if self.ram_enabled {
-                    let address = (0x1000 * self.ram_bank as u16) + (address - 0xa000);
-                    self.ram[address as usize]
-                } else {
-                    0
-                }
-            }
+            0x0000..=0x3fff => read_rom(&self.cart, self.low_rom_bank(), address),
+            0x4000..=0x7fff => read_rom(&self.cart, self.high_rom_bank(), address),
+            0xa000..=0xbfff => match ram_address(&self.ram, self.ram_bank(), address) {
+                Some(address) if self.ram_enabled => self.ram[address],
+                _ => 0xff,
+            },
             _ => unreachable!(),
         }
     }
 
     fn write(&mut self, address: u16, value: u8) {
         match address {
-            0x0000..=0x1fff => {
-                self.ram_enabled = (value & 0x0f) == 0x0a;
-                if !self.ram_enabled {
-                    handle_save(&self.cart, &self.ram).unwrap();
-                }
-            }
+            0x0000..=0x1fff => self.ram_enabled = (value & 0x0f) == 0x0a,
             0x2000..=0x3fff => {
-                let mut value = value & 0x1f;
-                value = match value {
-                    0x00 | 0x20 | 0x40 | 0x60 => value + 0x01,
-                    _ => value,
-                };
-                self.rom_bank = (self.rom_bank & 0xe0) | (value as usize & 0x1f)
-            }
-            0x4000..=0x5fff => {
-                if self.rom_ram_mode == 0x01 {
-                    self.ram_bank = value & 0x03;
-                } else {
-                    self.rom_bank = (self.rom_bank & 0xcf) | ((value as usize & 0x03) << 4);
-                }
-            }
-            0x6000..=0x7fff => {
-                self.rom_ram_mode = value & 0x01;
-                if self.rom_ram_mode == 0x00 {
-                    self.ram_bank = 0;
-                } else {
-                    self.rom_bank &= 0x1f;
+                self.lower_bank = match value & 0x1f {
+                    0x00 => 0x01,
+                    bank => bank,
                 }
             }
+            0x4000..=0x5fff => self.upper_bank = value & 0x03,
+            0x6000..=0x7fff => self.advanced_banking = value & 0x01 != 0,
             0xa000..=0xbfff => {
-                if self.ram_enabled {
-                    let address = (0x1000 * self.ram_bank as u16) + (address - 0xa000);
-                    self.ram[address as usize] = value;
+                if let Some(address) = ram_address(&self.ram, self.ram_bank(), address) {
+                    if self.ram_enabled {
+                        self.ram[address] = value;
+                    }
                 }
             }
             _ => unreachable!(),
         }
     }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        Some(self.ram.clone())
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        save_state(self)
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), state::Error> {
+        load_state(self, state, |mbc| &mut mbc.cart)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mbc::test::rom;
+
+    #[test]
+    fn advanced_banking() {
+        let mut mbc = MBC1::new(rom(0x03, 64, 0x03)).unwrap();
+        assert!(!mbc.multicart);
+        mbc.write(0x2000, 0x00);
+        assert_eq!(mbc.read(0x4000), 0x01);
+        mbc.write(0x4000, 0x01);
+        assert_eq!(mbc.read(0x4000), 0x21);
+        assert_eq!(mbc.read(0x0000), 0x00);
+
+        mbc.write(0x6000, 0x01);
+        assert_eq!(mbc.read(0x0000), 0x20);
+        mbc.write(0x0000, 0x0a);
+        mbc.write(0xa000, 0x42);
+        mbc.write(0x6000, 0x00);
+        assert_eq!(mbc.read(0xa000), 0x00);
+        mbc.write(0x6000, 0x01);
+        assert_eq!(mbc.read(0xa000), 0x42);
+    }
+
+    #[test]
+    fn multicart() {
+        let mut cart = rom(0x01, 64, 0x00);
+        let mut data = cart.data().clone();
+        for game in 0..4 {
+            let offset = game * 0x40000 + 0x0104;
+            data[offset..offset + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        }
+        cart = cart::Cartridge::new()
+            .with_lenient(true)
+            .with_data(&data)
+            .unwrap();
+        let mut mbc = MBC1::new(cart).unwrap();
+        assert!(mbc.multicart);
+        mbc.write(0x4000, 0x02);
+        mbc.write(0x2000, 0x13);
+        assert_eq!(mbc.read(0x4000), 0x23);
+        mbc.write(0x6000, 0x01);
+        assert_eq!(mbc.read(0x0000), 0x20);
+    }
 }
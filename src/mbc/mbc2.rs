@@ -1,7 +1,11 @@
 use crate::cart;
-use crate::mbc::{handle_save, prepare_save, MBC};
+use crate::mbc::{load_state, prepare_save, read_rom, save_state, MBC};
+use crate::state;
+use serde::{Deserialize, Serialize};
 
+#[derive(Serialize, Deserialize)]
 pub struct MBC2 {
+    #[serde(skip)]
     cart: cart::Cartridge,
     rom_bank: usize,
 
@@ -10,30 +14,29 @@ pub struct MBC2 {
 }
 
 impl MBC2 {
-    pub fn new(cart: cart::Cartridge) -> Self {
-        let ram = prepare_save(&cart, 512).unwrap();
-        Self {
+    pub fn new(cart: cart::Cartridge) -> Result<Self, cart::Error> {
+        let ram = prepare_save(&cart, 512)?;
+        Ok(Self {
             cart,
             rom_bank: 1,
             ram_enabled: false,
             ram,
-        }
+        })
     }
 }
 
 impl MBC for MBC2 {
     fn read(&self, address: u16) -> u8 {
         match address {
-            0x0000..=0x3fff => self.cart.data()[address as usize],
-            0x4000..=0x7fff => {
-                let address = (0x4000 * (self.rom_bank)) + (address as usize - 0x4000);
-                self.cart.data()[address]
-            }
-            0xa000..=0xa1ff => {
+            0x0000..=0x3fff => read_rom(&self.cart, 0, address),
+            0x4000..=0x7fff => read_rom(&self.cart, self.rom_bank, address),
+            // Only the lower nibble is backed by memory, the upper one floats high. The 512
+            // half-bytes are echoed through the whole external ram area.
+            0xa000..=0xbfff => {
                 if self.ram_enabled {
-                    self.ram[address as usize] & 0x0f
+                    self.ram[address as usize & 0x01ff] | 0xf0
                 } else {
-                    0
+                    0xff
                 }
             }
             _ => unreachable!(),
@@ -42,32 +45,103 @@ impl MBC for MBC2 {
 
     fn write(&mut self, address: u16, value: u8) {
         match address {
-            0x0000..=0x1fff => {
+            // Bit 8 of the address selects between the ram enable and rom bank registers.
+            0x0000..=0x3fff => {
                 if address & 0x0100 == 0 {
-                    self.ram_enabled = !self.ram_enabled;
-                }
-                if !self.ram_enabled {
-                    handle_save(&self.cart, &self.ram).unwrap();
-                }
-            }
-            0x2000..=0x3fff => {
-                self.rom_bank = if address & 0x0100 == 0 {
-                    self.rom_bank
+                    self.ram_enabled = (value & 0x0f) == 0x0a;
                 } else {
-                    let value = value as usize & 0x0f;
-                    match value {
-                        0x00 | 0x20 | 0x40 | 0x60 => value + 0x01,
-                        _ => value,
-                    }
-                } & 0x0f;
+                    self.rom_bank = match value & 0x0f {
+                        0x00 => 0x01,
+                        bank => bank as usize,
+                    };
+                }
             }
-            0xa000..=0xa1ff => {
+            0x4000..=0x7fff => {}
+            0xa000..=0xbfff => {
                 if self.ram_enabled {
-                    let address = address - 0xa000;
-                    self.ram[address as usize] = value & 0x0f;
+                    self.ram[address as usize & 0x01ff] = value & 0x0f;
                 }
             }
             _ => unreachable!(),
         }
     }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        Some(self.ram.clone())
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        save_state(self)
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), state::Error> {
+        load_state(self, state, |mbc| &mut mbc.cart)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mbc::test::rom;
+
+    fn mbc2() -> MBC2 {
+        MBC2::new(rom(0x05, 16, 0x00)).unwrap()
+    }
+
+    #[test]
+    fn register_selection() {
+        let mut mbc = mbc2();
+        assert_eq!(mbc.read(0x4000), 0x01);
+        mbc.write(0x2100, 0x05);
+        assert_eq!(mbc.read(0x4000), 0x05);
+        mbc.write(0x3fff, 0x0a);
+        assert_eq!(mbc.read(0x4000), 0x0a);
+        mbc.write(0x0100, 0x00);
+        assert_eq!(mbc.read(0x4000), 0x01);
+        mbc.write(0x2000, 0x0a);
+        assert_eq!(mbc.read(0x4000), 0x01);
+        assert!(mbc.ram_enabled);
+    }
+
+    #[test]
+    fn ram_enable() {
+        let mut mbc = mbc2();
+        mbc.write(0xa000, 0x03);
+        assert_eq!(mbc.read(0xa000), 0xff);
+        mbc.write(0x0000, 0x0a);
+        mbc.write(0xa000, 0x03);
+        assert_eq!(mbc.read(0xa000), 0xf3);
+        mbc.write(0x0000, 0x0a);
+        assert!(mbc.ram_enabled);
+        mbc.write(0x0000, 0x00);
+        assert_eq!(mbc.read(0xa000), 0xff);
+    }
+
+    #[test]
+    fn ram_echo() {
+        let mut mbc = mbc2();
+        mbc.write(0x0000, 0x0a);
+        mbc.write(0xa1ff, 0xab);
+        assert_eq!(mbc.read(0xa1ff), 0xfb);
+        assert_eq!(mbc.read(0xa3ff), 0xfb);
+        assert_eq!(mbc.read(0xbfff), 0xfb);
+        mbc.write(0xb000, 0x07);
+        assert_eq!(mbc.read(0xa000), 0xf7);
+    }
+
+    #[test]
+    fn rom_banks_wrap() {
+        let mut mbc = mbc2();
+        mbc.write(0x2100, 0x1f);
+        assert_eq!(mbc.read(0x4000), 0x0f);
+        assert_eq!(mbc.read(0x0000), 0x00);
+    }
 }
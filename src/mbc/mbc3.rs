@@ -1,55 +1,61 @@
 use crate::cart;
-use crate::mbc::{handle_save, prepare_save, MBC};
+use crate::mbc::{load_save, load_state, ram_address, read_rom, save_state, MBC, RTC};
+use crate::state;
+use crate::timer::Timing;
+use serde::{Deserialize, Serialize};
 
+#[derive(Serialize, Deserialize)]
 pub struct MBC3 {
+    #[serde(skip)]
     cart: cart::Cartridge,
     rom_bank: usize,
 
     ram_enabled: bool,
     ram_bank: u8,
     ram: Vec<u8>,
-    rtc: [u8; 5],
+    rtc: Option<RTC>,
 }
 
 impl MBC3 {
-    pub fn new(cart: cart::Cartridge) -> Self {
-        let ram_size = match cart.ram_size() {
-            0 => 0,
-            s => 0x1000 << s,
+    pub fn new(cart: cart::Cartridge) -> Result<Self, cart::Error> {
+        let ram_size = cart.ram_size();
+        let mut ram = load_save(&cart)?;
+        let rtc = if cart.cart_type().timer {
+            Some(RTC::with_save(ram.get(ram_size..).unwrap_or(&[])))
+        } else {
+            None
         };
-        let ram = prepare_save(&cart, ram_size).unwrap();
-        Self {
+        ram.resize(ram_size, 0);
+        Ok(Self {
             cart,
             rom_bank: 1,
 
             ram_enabled: false,
             ram_bank: 0,
             ram,
-            rtc: [0; 5],
-        }
+            rtc,
+        })
     }
 }
 
 impl MBC for MBC3 {
     fn read(&self, address: u16) -> u8 {
         match address {
-            0x0000..=0x3fff => self.cart.data()[address as usize],
-            0x4000..=0x7fff => {
-                let address = (0x4000 * (self.rom_bank)) + (address as usize - 0x4000);
-                self.cart.data()[address]
-            }
+            0x0000..=0x3fff => read_rom(&self.cart, 0, address),
+            0x4000..=0x7fff => read_rom(&self.cart, self.rom_bank, address),
             0xa000..=0xbfff => {
-                if self.ram_enabled {
-                    match self.ram_bank {
-                        0x00..=0x03 => {
-                            let address = (0x1000 * self.ram_bank as u16) + (address - 0xa000);
-                            self.ram[address as usize]
+                if !self.ram_enabled {
+                    return 0xff;
+                }
+                match (self.ram_bank, &self.rtc) {
+                    (0x00..=0x03, _) => {
+                        match ram_address(&self.ram, self.ram_bank as usize, address) {
+                            Some(address) => self.ram[address],
+                            None => 0xff,
                         }
-                        0x08..=0x0c => self.rtc[self.ram_bank as usize - 0x08],
-                        _ => unreachable!(),
                     }
-                } else {
-                    0
+                    (0x08..=0x0c, Some(rtc)) => rtc.read(self.ram_bank),
+                    _ => 0xff,
                 }
             }
             _ => unreachable!(),
@@ -58,23 +64,30 @@ impl MBC for MBC3 {
 
     fn write(&mut self, address: u16, value: u8) {
         match address {
-            0x0000..=0x1fff => {
-                self.ram_enabled = (value & 0x0f) == 0x0a;
-                if !self.ram_enabled {
-                    handle_save(&self.cart, &self.ram).unwrap();
+            0x0000..=0x1fff => self.ram_enabled = (value & 0x0f) == 0x0a,
+            0x2000..=0x3fff => {
+                self.rom_bank = match value & 0x7f {
+                    0x00 => 0x01,
+                    bank => bank as usize,
                 }
             }
-            0x2000..=0x3fff => self.rom_bank = (self.rom_bank & 0x80) | (value as usize & 0x7f),
             0x4000..=0x5fff => self.ram_bank = value & 0x0f,
-            0x6000..=0x7fff => {}
+            0x6000..=0x7fff => {
+                if let Some(rtc) = &mut self.rtc {
+                    rtc.latch(value);
+                }
+            }
             0xa000..=0xbfff => {
                 if self.ram_enabled {
-                    match self.ram_bank {
-                        0x00..=0x03 => {
-                            let address = (0x1000 * self.ram_bank as u16) + (address - 0xa000);
-                            self.ram[address as usize] = value;
+                    match (self.ram_bank, &mut self.rtc) {
+                        (0x00..=0x03, _) => {
+                            if let Some(address) =
+                                ram_address(&self.ram, self.ram_bank as usize, address)
+                            {
+                                self.ram[address] = value;
+                            }
                         }
-                        0x08..=0x0c => self.rtc[self.ram_bank as usize - 0x08] = value,
+                        (0x08..=0x0c, Some(rtc)) => rtc.write(self.ram_bank, value),
                         _ => {}
                     }
                 }
@@ -82,4 +95,33 @@ impl MBC for MBC3 {
             _ => unreachable!(),
         }
     }
+
+    fn advance(&mut self, timing: Timing) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.advance(timing);
+        }
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        Some(match &self.rtc {
+            Some(rtc) => [&self.ram[..], &rtc.save()].concat(),
+            None => self.ram.clone(),
+        })
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        save_state(self)
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), state::Error> {
+        load_state(self, state, |mbc| &mut mbc.cart)
+    }
 }
@@ -1,75 +1,125 @@
 use crate::cart;
-use crate::mbc::{handle_save, prepare_save, MBC};
+use crate::mbc::{load_state, prepare_save, ram_address, read_rom, save_state, MBC};
+use crate::state;
+use serde::{Deserialize, Serialize};
 
+#[derive(Serialize, Deserialize)]
 pub struct MBC5 {
+    #[serde(skip)]
     cart: cart::Cartridge,
     rom_bank: usize,
 
     ram_enabled: bool,
     ram_bank: usize,
     ram: Vec<u8>,
+
+    has_rumble: bool,
+    rumble: bool,
 }
 
 impl MBC5 {
-    pub fn new(cart: cart::Cartridge) -> Self {
-        let ram_size = match cart.ram_size() {
-            0 => 0,
-            s => 0x2000 << s,
-        };
-        let ram = prepare_save(&cart, ram_size).unwrap();
-        Self {
+    pub fn new(cart: cart::Cartridge) -> Result<Self, cart::Error> {
+        let ram = prepare_save(&cart, cart.ram_size())?;
+        let has_rumble = cart.cart_type().rumble;
+        Ok(Self {
             cart,
             rom_bank: 1,
 
             ram_enabled: false,
             ram_bank: 0,
             ram,
-        }
+
+            has_rumble,
+            rumble: false,
+        })
     }
 }
 
 impl MBC for MBC5 {
     fn read(&self, address: u16) -> u8 {
         match address {
-            0x0000..=0x3fff => self.cart.data()[address as usize],
-            0x4000..=0x7fff => {
-                let address = (0x4000 * (self.rom_bank)) + (address as usize - 0x4000);
-                self.cart.data()[address]
-            }
-            0xa000..=0xbfff => {
-                if self.ram_enabled {
-                    let address = (0x2000 * self.ram_bank as u16) + (address - 0xa000);
-                    self.ram[address as usize]
-                } else {
-                    0
-                }
-            }
+            0x0000..=0x3fff => read_rom(&self.cart, 0, address),
+            0x4000..=0x7fff => read_rom(&self.cart, self.rom_bank, address),
+            0xa000..=0xbfff => match ram_address(&self.ram, self.ram_bank, address) {
+                Some(address) if self.ram_enabled => self.ram[address],
+                _ => 0xff,
+            },
             _ => unreachable!(),
         }
     }
 
     fn write(&mut self, address: u16, value: u8) {
         match address {
-            0x0000..=0x1fff => {
-                self.ram_enabled = (value & 0x0f) == 0x0a;
-                if !self.ram_enabled {
-                    handle_save(&self.cart, &self.ram).unwrap();
-                }
-            }
+            0x0000..=0x1fff => self.ram_enabled = (value & 0x0f) == 0x0a,
             0x2000..=0x2fff => self.rom_bank = (self.rom_bank & 0xff00) | value as usize,
             0x3000..=0x3fff => {
                 self.rom_bank =
                     if value & 0x01 != 0 { 0x0100 } else { 0x0000 } | (self.rom_bank & 0x00ff)
             }
-            0x4000..=0x5fff => self.ram_bank = (value & 0x0f) as usize,
+            // Rumble carts wire the motor to bit 3 instead of the ram bank register.
+            0x4000..=0x5fff => {
+                if self.has_rumble {
+                    self.rumble = value & 0x08 != 0;
+                    self.ram_bank = (value & 0x07) as usize;
+                } else {
+                    self.ram_bank = (value & 0x0f) as usize;
+                }
+            }
             0x6000..=0x7fff => {}
             0xa000..=0xbfff => {
-                if self.ram_enabled {
-                    let address = (0x2000 * self.ram_bank) + ((address as usize) - 0xa000);
-                    self.ram[address] = value;
+                if let Some(address) = ram_address(&self.ram, self.ram_bank, address) {
+                    if self.ram_enabled {
+                        self.ram[address] = value;
+                    }
                 }
             }
             _ => unreachable!(),
         }
     }
+
+    fn rumble(&self) -> bool {
+        self.rumble
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        Some(self.ram.clone())
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        save_state(self)
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), state::Error> {
+        load_state(self, state, |mbc| &mut mbc.cart)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mbc::test::rom;
+
+    #[test]
+    fn rumble() {
+        let mut mbc = MBC5::new(rom(0x1d, 4, 0x03)).unwrap();
+        mbc.write(0x0000, 0x0a);
+        mbc.write(0x4000, 0x09);
+        assert!(mbc.rumble());
+        assert_eq!(mbc.ram_bank, 0x01);
+        mbc.write(0x4000, 0x01);
+        assert!(!mbc.rumble());
+
+        let mut mbc = MBC5::new(rom(0x1a, 4, 0x04)).unwrap();
+        mbc.write(0x4000, 0x09);
+        assert!(!mbc.rumble());
+        assert_eq!(mbc.ram_bank, 0x09);
+    }
 }
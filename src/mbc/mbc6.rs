@@ -0,0 +1,250 @@
+use crate::cart;
+use crate::mbc::{load_save, load_state, read_rom, save_state, MBC};
+use crate::state;
+use serde::{Deserialize, Serialize};
+
+const FLASH_SIZE: usize = 0x100000;
+const FLASH_SECTOR_SIZE: usize = 0x20000;
+
+#[derive(Serialize, Deserialize)]
+pub struct MBC6 {
+    #[serde(skip)]
+    cart: cart::Cartridge,
+    // The rom and ram areas are both split into two independently switchable halves, A and B.
+    rom_banks: [usize; 2],
+    flash_selected: [bool; 2],
+
+    ram_enabled: bool,
+    ram_banks: [usize; 2],
+    ram: Vec<u8>,
+
+    flash_enabled: bool,
+    flash_write_enabled: bool,
+    flash: Flash,
+}
+
+impl MBC6 {
+    pub fn new(cart: cart::Cartridge) -> Result<Self, cart::Error> {
+        let ram_size = cart.ram_size();
+        let mut ram = load_save(&cart)?;
+        let flash = Flash::new(ram.get(ram_size..).unwrap_or(&[]));
+        ram.resize(ram_size, 0);
+        Ok(Self {
+            cart,
+            rom_banks: [0; 2],
+            flash_selected: [false; 2],
+
+            ram_enabled: false,
+            ram_banks: [0; 2],
+            ram,
+
+            flash_enabled: false,
+            flash_write_enabled: false,
+            flash,
+        })
+    }
+
+    fn ram_address(&self, address: u16) -> Option<usize> {
+        if self.ram.is_empty() {
+            return None;
+        }
+        let bank = self.ram_banks[(address as usize >> 12) & 0x01];
+        Some((bank * 0x1000 + (address as usize & 0x0fff)) % self.ram.len())
+    }
+}
+
+impl MBC for MBC6 {
+    fn read(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3fff => read_rom(&self.cart, 0, address),
+            0x4000..=0x7fff => {
+                let half = (address as usize >> 13) & 0x01;
+                let bank = self.rom_banks[half];
+                if self.flash_selected[half] {
+                    if self.flash_enabled {
+                        self.flash.read(bank * 0x2000 + (address as usize & 0x1fff))
+                    } else {
+                        0xff
+                    }
+                } else {
+                    read_rom(
+                        &self.cart,
+                        bank >> 1,
+                        ((bank as u16 & 0x01) << 13) | (address & 0x1fff),
+                    )
+                }
+            }
+            0xa000..=0xbfff => match self.ram_address(address) {
+                Some(address) if self.ram_enabled => self.ram[address],
+                _ => 0xff,
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x03ff => self.ram_enabled = (value & 0x0f) == 0x0a,
+            0x0400..=0x07ff => self.ram_banks[0] = (value & 0x07) as usize,
+            0x0800..=0x0bff => self.ram_banks[1] = (value & 0x07) as usize,
+            0x0c00..=0x0fff => self.flash_enabled = value & 0x01 != 0,
+            0x1000 => self.flash_write_enabled = value & 0x01 != 0,
+            0x1001..=0x1fff => {}
+            0x2000..=0x27ff => self.rom_banks[0] = (value & 0x7f) as usize,
+            0x2800..=0x2fff => self.flash_selected[0] = value == 0x08,
+            0x3000..=0x37ff => self.rom_banks[1] = (value & 0x7f) as usize,
+            0x3800..=0x3fff => self.flash_selected[1] = value == 0x08,
+            0x4000..=0x7fff => {
+                let half = (address as usize >> 13) & 0x01;
+                if self.flash_selected[half] && self.flash_enabled && self.flash_write_enabled {
+                    let address = self.rom_banks[half] * 0x2000 + (address as usize & 0x1fff);
+                    self.flash.write(address, value);
+                }
+            }
+            0xa000..=0xbfff => {
+                if let Some(address) = self.ram_address(address) {
+                    if self.ram_enabled {
+                        self.ram[address] = value;
+                    }
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        Some([&self.ram[..], &self.flash.data[..]].concat())
+    }
+
+    fn save_changed(&mut self) -> bool {
+        std::mem::take(&mut self.flash.changed)
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        save_state(self)
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), state::Error> {
+        load_state(self, state, |mbc| &mut mbc.cart)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+enum FlashState {
+    Read,
+    Unlock1,
+    Unlock2,
+    Program,
+    EraseSetup,
+    EraseUnlock1,
+    EraseUnlock2,
+}
+
+// A simplified model of the Macronix flash chip, supporting the unlock sequence followed by
+// byte programming, sector erase and chip erase.
+#[derive(Serialize, Deserialize)]
+struct Flash {
+    data: Vec<u8>,
+    state: FlashState,
+    #[serde(skip)]
+    changed: bool,
+}
+
+impl Flash {
+    fn new(data: &[u8]) -> Self {
+        let mut data = data.to_vec();
+        data.resize(FLASH_SIZE, 0xff);
+        Self {
+            data,
+            state: FlashState::Read,
+            changed: false,
+        }
+    }
+
+    fn read(&self, address: usize) -> u8 {
+        self.data[address % FLASH_SIZE]
+    }
+
+    fn write(&mut self, address: usize, value: u8) {
+        let address = address % FLASH_SIZE;
+        let command = address & 0x7fff;
+        self.state = match (self.state, command, value) {
+            (FlashState::Program, _, _) => {
+                self.data[address] &= value;
+                self.changed = true;
+                FlashState::Read
+            }
+            (_, _, 0xf0) => FlashState::Read,
+            (FlashState::Read, 0x5555, 0xaa) => FlashState::Unlock1,
+            (FlashState::Unlock1, 0x2aaa, 0x55) => FlashState::Unlock2,
+            (FlashState::Unlock2, 0x5555, 0xa0) => FlashState::Program,
+            (FlashState::Unlock2, 0x5555, 0x80) => FlashState::EraseSetup,
+            (FlashState::EraseSetup, 0x5555, 0xaa) => FlashState::EraseUnlock1,
+            (FlashState::EraseUnlock1, 0x2aaa, 0x55) => FlashState::EraseUnlock2,
+            (FlashState::EraseUnlock2, 0x5555, 0x10) => {
+                self.data.iter_mut().for_each(|b| *b = 0xff);
+                self.changed = true;
+                FlashState::Read
+            }
+            (FlashState::EraseUnlock2, _, 0x30) => {
+                let sector = address / FLASH_SECTOR_SIZE * FLASH_SECTOR_SIZE;
+                self.data[sector..sector + FLASH_SECTOR_SIZE]
+                    .iter_mut()
+                    .for_each(|b| *b = 0xff);
+                self.changed = true;
+                FlashState::Read
+            }
+            _ => FlashState::Read,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mbc::test::rom;
+
+    #[test]
+    fn split_banking() {
+        let mut mbc = MBC6::new(rom(0x20, 8, 0x03)).unwrap();
+        mbc.write(0x2000, 0x04);
+        mbc.write(0x3000, 0x06);
+        assert_eq!(mbc.read(0x4000), 0x02);
+        assert_eq!(mbc.read(0x6000), 0x03);
+
+        mbc.write(0x0000, 0x0a);
+        mbc.write(0x0400, 0x01);
+        mbc.write(0x0800, 0x02);
+        mbc.write(0xb000, 0x42);
+        mbc.write(0x0400, 0x02);
+        assert_eq!(mbc.read(0xa000), 0x42);
+    }
+
+    #[test]
+    fn flash_program() {
+        let mut mbc = MBC6::new(rom(0x20, 8, 0x03)).unwrap();
+        mbc.write(0x0c00, 0x01);
+        mbc.write(0x1000, 0x01);
+        mbc.write(0x2800, 0x08);
+        let mut command = |bank: u8, address: u16, value: u8| {
+            mbc.write(0x2000, bank);
+            mbc.write(0x4000 | (address & 0x1fff), value);
+        };
+        command(0x02, 0x5555, 0xaa);
+        command(0x01, 0x2aaa, 0x55);
+        command(0x02, 0x5555, 0xa0);
+        command(0x10, 0x0010, 0x5a);
+        assert_eq!(mbc.read(0x4010), 0x5a);
+        assert_eq!(mbc.read(0x4011), 0xff);
+        assert!(mbc.save_changed());
+        assert!(!mbc.save_changed());
+    }
+}
@@ -0,0 +1,354 @@
+use crate::cart;
+use crate::mbc::{load_state, prepare_save, read_rom, save_state, MBC};
+use crate::state;
+use serde::{Deserialize, Serialize};
+
+const ACCELEROMETER_CENTER: f32 = 0x81d0 as f32;
+// Roughly the change in the accelerometer reading for a tilt of 1g.
+const ACCELEROMETER_GRAVITY: f32 = 0x70 as f32;
+const EEPROM_SIZE: usize = 256;
+
+#[derive(Serialize, Deserialize)]
+pub struct MBC7 {
+    #[serde(skip)]
+    cart: cart::Cartridge,
+    rom_bank: usize,
+
+    // The register area is only accessible after writing to both enable registers.
+    ram_enabled: bool,
+    registers_enabled: bool,
+
+    tilt: (f32, f32),
+    latch_ready: bool,
+    latched: (u16, u16),
+    eeprom: EEPROM,
+}
+
+impl MBC7 {
+    pub fn new(cart: cart::Cartridge) -> Result<Self, cart::Error> {
+        let eeprom = EEPROM::new(prepare_save(&cart, EEPROM_SIZE)?);
+        Ok(Self {
+            cart,
+            rom_bank: 1,
+
+            ram_enabled: false,
+            registers_enabled: false,
+
+            tilt: (0.0, 0.0),
+            latch_ready: false,
+            latched: (0x8000, 0x8000),
+            eeprom,
+        })
+    }
+
+    fn accelerometer(&self) -> (u16, u16) {
+        let axis = |tilt: f32| (ACCELEROMETER_CENTER + tilt * ACCELEROMETER_GRAVITY) as u16;
+        (axis(self.tilt.0), axis(self.tilt.1))
+    }
+}
+
+impl MBC for MBC7 {
+    fn read(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3fff => read_rom(&self.cart, 0, address),
+            0x4000..=0x7fff => read_rom(&self.cart, self.rom_bank, address),
+            0xa000..=0xafff if self.ram_enabled && self.registers_enabled => {
+                match address & 0x00f0 {
+                    0x20 => self.latched.0 as u8,
+                    0x30 => (self.latched.0 >> 8) as u8,
+                    0x40 => self.latched.1 as u8,
+                    0x50 => (self.latched.1 >> 8) as u8,
+                    0x60 => 0x00,
+                    0x80 => self.eeprom.pins(),
+                    _ => 0xff,
+                }
+            }
+            0xa000..=0xbfff => 0xff,
+            _ => unreachable!(),
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1fff => self.ram_enabled = (value & 0x0f) == 0x0a,
+            0x2000..=0x3fff => self.rom_bank = (value & 0x7f) as usize,
+            0x4000..=0x5fff => self.registers_enabled = value == 0x40,
+            0x6000..=0x7fff => {}
+            0xa000..=0xafff if self.ram_enabled && self.registers_enabled => {
+                match (address & 0x00f0, value) {
+                    (0x00, 0x55) => {
+                        self.latched = (0x8000, 0x8000);
+                        self.latch_ready = true;
+                    }
+                    (0x10, 0xaa) if self.latch_ready => {
+                        self.latched = self.accelerometer();
+                        self.latch_ready = false;
+                    }
+                    (0x80, _) => self.eeprom.set_pins(value),
+                    _ => {}
+                }
+            }
+            0xa000..=0xbfff => {}
+            _ => unreachable!(),
+        }
+    }
+
+    fn tilt(&mut self, x: f32, y: f32) {
+        self.tilt = (x, y);
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        Some(self.eeprom.data.clone())
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        save_state(self)
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), state::Error> {
+        load_state(self, state, |mbc| &mut mbc.cart)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+enum EEPROMState {
+    Idle,
+    Command,
+    Read {
+        word: u16,
+        bit: u8,
+    },
+    Write {
+        address: Option<u8>,
+        word: u16,
+        bit: u8,
+    },
+}
+
+// The 93LC56 serial EEPROM, holding 128 16-bit words. Commands are shifted in one bit per
+// rising clock edge while chip select is held high: a start bit, a two bit opcode and an eight
+// bit address, optionally followed by a data word.
+#[derive(Serialize, Deserialize)]
+struct EEPROM {
+    data: Vec<u8>,
+    state: EEPROMState,
+    command: u16,
+    bits: u8,
+    write_enabled: bool,
+
+    cs: bool,
+    clk: bool,
+    di: bool,
+    out: bool,
+}
+
+impl EEPROM {
+    fn new(data: Vec<u8>) -> Self {
+        Self {
+            data,
+            state: EEPROMState::Idle,
+            command: 0,
+            bits: 0,
+            write_enabled: false,
+            cs: false,
+            clk: false,
+            di: false,
+            out: true,
+        }
+    }
+
+    fn pins(&self) -> u8 {
+        (if self.cs { 0x80 } else { 0x00 })
+            | (if self.clk { 0x40 } else { 0x00 })
+            | (if self.di { 0x02 } else { 0x00 })
+            | (if self.out { 0x01 } else { 0x00 })
+    }
+
+    fn set_pins(&mut self, value: u8) {
+        let cs = value & 0x80 != 0;
+        let clk = value & 0x40 != 0;
+        let di = value & 0x02 != 0;
+        let rising = clk && !self.clk;
+        self.cs = cs;
+        self.clk = clk;
+        self.di = di;
+        if !cs {
+            self.state = EEPROMState::Idle;
+        } else if rising {
+            self.clock(di);
+        }
+    }
+
+    fn clock(&mut self, di: bool) {
+        match self.state {
+            EEPROMState::Idle => {
+                if di {
+                    self.state = EEPROMState::Command;
+                    self.command = 0;
+                    self.bits = 0;
+                }
+                self.out = true;
+            }
+            EEPROMState::Command => {
+                self.command = (self.command << 1) | di as u16;
+                self.bits += 1;
+                if self.bits == 10 {
+                    self.execute();
+                }
+            }
+            EEPROMState::Read { word, bit } => {
+                self.out = word & (0x8000 >> bit) != 0;
+                self.state = if bit == 15 {
+                    EEPROMState::Idle
+                } else {
+                    EEPROMState::Read { word, bit: bit + 1 }
+                };
+            }
+            EEPROMState::Write { address, word, bit } => {
+                let word = (word << 1) | di as u16;
+                if bit < 15 {
+                    self.state = EEPROMState::Write {
+                        address,
+                        word,
+                        bit: bit + 1,
+                    };
+                    return;
+                }
+                self.state = EEPROMState::Idle;
+                self.out = true;
+                if !self.write_enabled {
+                    return;
+                }
+                match address {
+                    Some(address) => self.write_word(address, word),
+                    None => (0..0x80).for_each(|address| self.write_word(address, word)),
+                }
+            }
+        }
+    }
+
+    fn execute(&mut self) {
+        let address = (self.command & 0x7f) as u8;
+        self.state = EEPROMState::Idle;
+        match (self.command >> 8) & 0x03 {
+            0b10 => {
+                self.state = EEPROMState::Read {
+                    word: self.read_word(address),
+                    bit: 0,
+                };
+                // A dummy zero bit is shifted out before the data.
+                self.out = false;
+            }
+            0b01 => {
+                self.state = EEPROMState::Write {
+                    address: Some(address),
+                    word: 0,
+                    bit: 0,
+                }
+            }
+            0b11 => {
+                if self.write_enabled {
+                    self.write_word(address, 0xffff);
+                }
+            }
+            _ => match (self.command >> 6) & 0x03 {
+                0b00 => self.write_enabled = false,
+                0b01 => {
+                    self.state = EEPROMState::Write {
+                        address: None,
+                        word: 0,
+                        bit: 0,
+                    }
+                }
+                0b10 => {
+                    if self.write_enabled {
+                        self.data.iter_mut().for_each(|b| *b = 0xff);
+                    }
+                }
+                _ => self.write_enabled = true,
+            },
+        }
+    }
+
+    fn read_word(&self, address: u8) -> u16 {
+        let address = address as usize * 2;
+        ((self.data[address + 1] as u16) << 8) | self.data[address] as u16
+    }
+
+    fn write_word(&mut self, address: u8, word: u16) {
+        let address = address as usize * 2;
+        self.data[address] = word as u8;
+        self.data[address + 1] = (word >> 8) as u8;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mbc::test::rom;
+
+    fn send(mbc: &mut MBC7, bits: &[u8]) {
+        for bit in bits {
+            mbc.write(0xa080, 0x80 | (bit << 1));
+            mbc.write(0xa080, 0xc0 | (bit << 1));
+        }
+    }
+
+    fn receive(mbc: &mut MBC7) -> u16 {
+        let mut word = 0;
+        for _ in 0..16 {
+            mbc.write(0xa080, 0x80);
+            mbc.write(0xa080, 0xc0);
+            word = (word << 1) | (mbc.read(0xa080) & 0x01) as u16;
+        }
+        word
+    }
+
+    fn mbc7() -> MBC7 {
+        let mut mbc = MBC7::new(rom(0x22, 4, 0x00)).unwrap();
+        mbc.write(0x0000, 0x0a);
+        mbc.write(0x4000, 0x40);
+        mbc
+    }
+
+    #[test]
+    fn accelerometer() {
+        let mut mbc = mbc7();
+        mbc.tilt(1.0, -0.5);
+        mbc.write(0xa010, 0xaa);
+        assert_eq!(mbc.read(0xa020), 0x00);
+        assert_eq!(mbc.read(0xa030), 0x80);
+        mbc.write(0xa000, 0x55);
+        mbc.write(0xa010, 0xaa);
+        assert_eq!(mbc.read(0xa020), 0x40);
+        assert_eq!(mbc.read(0xa030), 0x82);
+        assert_eq!(mbc.read(0xa040), 0x98);
+        assert_eq!(mbc.read(0xa050), 0x81);
+    }
+
+    #[test]
+    fn eeprom_write_and_read() {
+        let mut mbc = mbc7();
+        let address = [0, 0, 0, 0, 0, 1, 0, 1];
+        let word = [1, 0, 1, 0, 0, 1, 0, 1, 1, 1, 0, 0, 0, 0, 1, 1];
+
+        send(&mut mbc, &[1, 0, 1]);
+        send(&mut mbc, &address);
+        send(&mut mbc, &word);
+        mbc.write(0xa080, 0x00);
+
+        send(&mut mbc, &[1, 0, 0, 1, 1, 0, 0, 0, 0, 0, 0]);
+        mbc.write(0xa080, 0x00);
+        send(&mut mbc, &[1, 0, 1]);
+        send(&mut mbc, &address);
+        send(&mut mbc, &word);
+        mbc.write(0xa080, 0x00);
+
+        send(&mut mbc, &[1, 1, 0]);
+        send(&mut mbc, &address);
+        assert_eq!(mbc.read(0xa080) & 0x01, 0x00);
+        assert_eq!(receive(&mut mbc), 0xa5c3);
+        assert_eq!(mbc.eeprom.read_word(0x05), 0xa5c3);
+        assert_eq!(mbc.eeprom.read_word(0x06), 0x0000);
+    }
+}
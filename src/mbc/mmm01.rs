@@ -0,0 +1,182 @@
+use crate::cart;
+use crate::mbc::{load_state, prepare_save, ram_address, read_rom, save_state, MBC};
+use crate::state;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+pub struct MMM01 {
+    #[serde(skip)]
+    cart: cart::Cartridge,
+
+    // Until the menu sets the map bit, the last 32 KiB of rom are mapped and every register
+    // write may also change the outer bank bits and masks, which are frozen afterwards.
+    mapped: bool,
+    rom_bank: usize,
+    rom_mask: usize,
+
+    ram_enabled: bool,
+    ram_bank: usize,
+    ram_mask: usize,
+    ram: Vec<u8>,
+}
+
+impl MMM01 {
+    pub fn new(cart: cart::Cartridge) -> Result<Self, cart::Error> {
+        let ram = prepare_save(&cart, cart.ram_size())?;
+        Ok(Self {
+            cart,
+
+            mapped: false,
+            rom_bank: 0,
+            rom_mask: 0,
+
+            ram_enabled: false,
+            ram_bank: 0,
+            ram_mask: 0,
+            ram,
+        })
+    }
+
+    // The bits of the rom bank number the game itself is allowed to change.
+    fn rom_writable(&self) -> usize {
+        0x1f & !(self.rom_mask << 1)
+    }
+
+    fn ram_writable(&self) -> usize {
+        0x03 & !self.ram_mask
+    }
+
+    fn low_rom_bank(&self) -> usize {
+        if self.mapped {
+            self.rom_bank & !self.rom_writable()
+        } else {
+            self.banks().saturating_sub(2)
+        }
+    }
+
+    fn high_rom_bank(&self) -> usize {
+        if !self.mapped {
+            return self.banks().saturating_sub(1);
+        }
+        if self.rom_bank & self.rom_writable() == 0 {
+            self.rom_bank | 0x01
+        } else {
+            self.rom_bank
+        }
+    }
+
+    fn banks(&self) -> usize {
+        self.cart.data().len() / 0x4000
+    }
+}
+
+impl MBC for MMM01 {
+    fn read(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3fff => read_rom(&self.cart, self.low_rom_bank(), address),
+            0x4000..=0x7fff => read_rom(&self.cart, self.high_rom_bank(), address),
+            0xa000..=0xbfff => {
+                if !self.ram_enabled {
+                    return 0xff;
+                }
+                match ram_address(&self.ram, self.ram_bank, address) {
+                    Some(address) => self.ram[address],
+                    None => 0xff,
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        let value = value as usize;
+        match address {
+            0x0000..=0x1fff => {
+                self.ram_enabled = (value & 0x0f) == 0x0a;
+                if !self.mapped {
+                    self.ram_mask = (value >> 4) & 0x03;
+                    self.mapped = value & 0x40 != 0;
+                }
+            }
+            0x2000..=0x3fff => {
+                let writable = if self.mapped {
+                    self.rom_writable()
+                } else {
+                    0x7f
+                };
+                self.rom_bank = (self.rom_bank & !writable) | (value & writable);
+            }
+            0x4000..=0x5fff => {
+                if self.mapped {
+                    let writable = self.ram_writable();
+                    self.ram_bank = (self.ram_bank & !writable) | (value & writable);
+                } else {
+                    self.ram_bank = value & 0x0f;
+                    self.rom_bank = (self.rom_bank & 0x7f) | ((value & 0x30) << 3);
+                }
+            }
+            0x6000..=0x7fff => {
+                if !self.mapped {
+                    self.rom_mask = (value >> 2) & 0x0f;
+                }
+            }
+            0xa000..=0xbfff => {
+                if !self.ram_enabled {
+                    return;
+                }
+                if let Some(address) = ram_address(&self.ram, self.ram_bank, address) {
+                    self.ram[address] = value as u8;
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        Some(self.ram.clone())
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        save_state(self)
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), state::Error> {
+        load_state(self, state, |mbc| &mut mbc.cart)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mbc::test::rom;
+
+    #[test]
+    fn boots_into_menu_and_maps_game() {
+        let mut mbc = MMM01::new(rom(0x0b, 32, 0x00)).unwrap();
+        assert_eq!(mbc.read(0x0000), 30);
+        assert_eq!(mbc.read(0x4000), 31);
+
+        // Select the game at bank 8 with 4 banks, then map it.
+        mbc.write(0x2000, 0x08);
+        mbc.write(0x6000, 0x0e << 2);
+        mbc.write(0x0000, 0x40);
+        assert_eq!(mbc.read(0x0000), 8);
+        assert_eq!(mbc.read(0x4000), 9);
+
+        mbc.write(0x2000, 0x03);
+        assert_eq!(mbc.read(0x4000), 11);
+        mbc.write(0x2000, 0x1f);
+        assert_eq!(mbc.read(0x4000), 11);
+        mbc.write(0x6000, 0x00);
+        mbc.write(0x2000, 0x02);
+        assert_eq!(mbc.read(0x4000), 10);
+    }
+}
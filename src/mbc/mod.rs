@@ -1,15 +1,31 @@
 mod boot;
+mod camera;
+mod huc1;
+mod huc3;
 mod mbc;
 mod mbc1;
 mod mbc2;
 mod mbc3;
 mod mbc5;
+mod mbc6;
+mod mbc7;
+mod mmm01;
 mod none;
+mod rtc;
+mod tama5;
 
 pub use boot::*;
+pub use camera::*;
+pub use huc1::*;
+pub use huc3::*;
 pub use mbc::*;
 pub use mbc1::*;
 pub use mbc2::*;
 pub use mbc3::*;
 pub use mbc5::*;
+pub use mbc6::*;
+pub use mbc7::*;
+pub use mmm01::*;
 pub use none::*;
+pub use rtc::*;
+pub use tama5::*;
@@ -1,20 +1,59 @@
 use crate::cart;
-use crate::mbc::MBC;
+use crate::mbc::{load_state, prepare_save, ram_address, read_rom, save_state, MBC};
+use crate::state;
+use serde::{Deserialize, Serialize};
 
+#[derive(Serialize, Deserialize)]
 pub struct None {
+    #[serde(skip)]
     cart: cart::Cartridge,
+    ram: Vec<u8>,
 }
 
 impl None {
-    pub fn new(cart: cart::Cartridge) -> Self {
-        Self { cart }
+    pub fn new(cart: cart::Cartridge) -> Result<Self, cart::Error> {
+        let ram = prepare_save(&cart, cart.ram_size())?;
+        Ok(Self { cart, ram })
     }
 }
 
 impl MBC for None {
     fn read(&self, address: u16) -> u8 {
-        self.cart.data()[address as usize]
+        match address {
+            0x0000..=0x7fff => read_rom(&self.cart, (address >> 14) as usize, address),
+            0xa000..=0xbfff => match ram_address(&self.ram, 0, address) {
+                Some(address) => self.ram[address],
+                _ => 0xff,
+            },
+            _ => unreachable!(),
+        }
     }
 
-    fn write(&mut self, _address: u16, _value: u8) {}
+    fn write(&mut self, address: u16, value: u8) {
+        if let 0xa000..=0xbfff = address {
+            if let Some(address) = ram_address(&self.ram, 0, address) {
+                self.ram[address] = value;
+            }
+        }
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        Some(self.ram.clone())
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        save_state(self)
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), state::Error> {
+        load_state(self, state, |mbc| &mut mbc.cart)
+    }
 }
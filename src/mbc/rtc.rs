@@ -0,0 +1,240 @@
+use crate::timer::Timing;
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const CYCLES_PER_SECOND: u32 = 4_194_304;
+pub const RTC_SAVE_SIZE: usize = 48;
+
+#[derive(Serialize, Deserialize)]
+pub struct RTC {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    days: u16,
+    halt: bool,
+    carry: bool,
+
+    cycles: u32,
+    latched: [u8; 5],
+    latch_ready: bool,
+}
+
+impl RTC {
+    pub fn new() -> Self {
+        Self {
+            seconds: 0,
+            minutes: 0,
+            hours: 0,
+            days: 0,
+            halt: false,
+            carry: false,
+            cycles: 0,
+            latched: [0; 5],
+            latch_ready: false,
+        }
+    }
+
+    // Restores the clock from the common 48 byte footer: the live and latched registers as
+    // little endian u32s, followed by the unix timestamp of when the save was written.
+    pub fn with_save(data: &[u8]) -> Self {
+        let mut rtc = Self::new();
+        if data.len() < RTC_SAVE_SIZE {
+            return rtc;
+        }
+        let word = |i: usize| data[i * 4];
+        rtc.set_registers([word(0), word(1), word(2), word(3), word(4)]);
+        for i in 0..5 {
+            rtc.latched[i] = word(5 + i);
+        }
+        let saved = u64::from_le_bytes(data[40..48].try_into().unwrap());
+        let elapsed = unix_time().saturating_sub(saved);
+        rtc.advance_seconds(elapsed);
+        rtc
+    }
+
+    pub fn save(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(RTC_SAVE_SIZE);
+        for value in self.registers().iter().chain(self.latched.iter()) {
+            data.extend_from_slice(&(*value as u32).to_le_bytes());
+        }
+        data.extend_from_slice(&unix_time().to_le_bytes());
+        data
+    }
+
+    pub fn advance(&mut self, timing: Timing) {
+        if self.halt {
+            return;
+        }
+        self.cycles += timing as u32;
+        while self.cycles >= CYCLES_PER_SECOND {
+            self.cycles -= CYCLES_PER_SECOND;
+            self.tick();
+        }
+    }
+
+    pub fn latch(&mut self, value: u8) {
+        if self.latch_ready && value == 0x01 {
+            self.latched = self.registers();
+        }
+        self.latch_ready = value == 0x00;
+    }
+
+    pub fn read(&self, register: u8) -> u8 {
+        self.latched[register as usize - 0x08]
+    }
+
+    pub fn write(&mut self, register: u8, value: u8) {
+        let mut registers = self.registers();
+        registers[register as usize - 0x08] = value;
+        if register == 0x08 {
+            self.cycles = 0;
+        }
+        self.set_registers(registers);
+    }
+
+    fn advance_seconds(&mut self, mut seconds: u64) {
+        if self.halt {
+            return;
+        }
+        // Registers set past their range count up to their wraparound without carrying, which
+        // takes at most a few hours of single steps.
+        while seconds > 0 && (self.seconds >= 60 || self.minutes >= 60 || self.hours >= 24) {
+            self.tick();
+            seconds -= 1;
+        }
+        let seconds = self.seconds as u64 + seconds;
+        self.seconds = (seconds % 60) as u8;
+        let minutes = self.minutes as u64 + seconds / 60;
+        self.minutes = (minutes % 60) as u8;
+        let hours = self.hours as u64 + minutes / 60;
+        self.hours = (hours % 24) as u8;
+        let days = self.days as u64 + hours / 24;
+        if days > 0x1ff {
+            self.carry = true;
+        }
+        self.days = (days & 0x1ff) as u16;
+    }
+
+    fn tick(&mut self) {
+        self.seconds = (self.seconds + 1) & 0x3f;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+        self.minutes = (self.minutes + 1) & 0x3f;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+        self.hours = (self.hours + 1) & 0x1f;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+        self.days += 1;
+        if self.days > 0x1ff {
+            self.days = 0;
+            self.carry = true;
+        }
+    }
+
+    fn registers(&self) -> [u8; 5] {
+        [
+            self.seconds,
+            self.minutes,
+            self.hours,
+            (self.days & 0xff) as u8,
+            ((self.days >> 8) as u8 & 0x01)
+                | (if self.halt { 0x40 } else { 0x00 })
+                | (if self.carry { 0x80 } else { 0x00 }),
+        ]
+    }
+
+    fn set_registers(&mut self, registers: [u8; 5]) {
+        self.seconds = registers[0] & 0x3f;
+        self.minutes = registers[1] & 0x3f;
+        self.hours = registers[2] & 0x1f;
+        self.days = ((registers[4] as u16 & 0x01) << 8) | registers[3] as u16;
+        self.halt = registers[4] & 0x40 != 0;
+        self.carry = registers[4] & 0x80 != 0;
+    }
+}
+
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn advance_seconds(rtc: &mut RTC, seconds: u32) {
+        for _ in 0..seconds {
+            for _ in 0..(CYCLES_PER_SECOND / 0x4000) {
+                rtc.advance(0x4000);
+            }
+        }
+    }
+
+    #[test]
+    fn ticks_and_carries() {
+        let mut rtc = RTC::new();
+        rtc.set_registers([58, 59, 23, 0xff, 0x01]);
+        advance_seconds(&mut rtc, 2);
+        assert_eq!(rtc.registers(), [0, 0, 0, 0, 0x80]);
+    }
+
+    #[test]
+    fn halt_stops_clock() {
+        let mut rtc = RTC::new();
+        rtc.write(0x0c, 0x40);
+        advance_seconds(&mut rtc, 3);
+        assert_eq!(rtc.registers()[0], 0);
+        rtc.write(0x0c, 0x00);
+        advance_seconds(&mut rtc, 3);
+        assert_eq!(rtc.registers()[0], 3);
+    }
+
+    #[test]
+    fn latching() {
+        let mut rtc = RTC::new();
+        advance_seconds(&mut rtc, 5);
+        assert_eq!(rtc.read(0x08), 0);
+        rtc.latch(0x01);
+        assert_eq!(rtc.read(0x08), 0);
+        rtc.latch(0x00);
+        rtc.latch(0x01);
+        assert_eq!(rtc.read(0x08), 5);
+        advance_seconds(&mut rtc, 5);
+        assert_eq!(rtc.read(0x08), 5);
+    }
+
+    #[test]
+    fn save_roundtrip() {
+        let mut rtc = RTC::new();
+        rtc.set_registers([10, 20, 5, 0x23, 0x41]);
+        rtc.latch(0x00);
+        rtc.latch(0x01);
+        let data = rtc.save();
+        assert_eq!(data.len(), RTC_SAVE_SIZE);
+        let restored = RTC::with_save(&data);
+        assert_eq!(restored.registers(), [10, 20, 5, 0x23, 0x41]);
+        assert_eq!(restored.latched, [10, 20, 5, 0x23, 0x41]);
+    }
+
+    #[test]
+    fn catches_up_arithmetically() {
+        let mut rtc = RTC::new();
+        rtc.set_registers([62, 59, 23, 0xff, 0x01]);
+        rtc.advance_seconds(2 + 86400 * 3 + 61);
+        assert_eq!(rtc.registers(), [1, 0, 0, 0x03, 0x80]);
+
+        let mut rtc = RTC::new();
+        rtc.advance_seconds(u32::MAX as u64);
+        assert!(rtc.carry);
+    }
+}
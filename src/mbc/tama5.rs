@@ -0,0 +1,353 @@
+use crate::cart;
+use crate::mbc::{load_save, load_state, read_rom, save_state, unix_time, MBC};
+use crate::state;
+use crate::timer::Timing;
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+
+const CYCLES_PER_SECOND: u32 = 4_194_304;
+const MEMORY_SIZE: usize = 0x20;
+const CLOCK_SAVE_SIZE: usize = 16;
+
+#[derive(Serialize, Deserialize)]
+pub struct TAMA5 {
+    #[serde(skip)]
+    cart: cart::Cartridge,
+    rom_bank: usize,
+
+    // Everything is accessed through nibble wide registers: 0xa001 selects one, and 0xa000
+    // reads or writes it. Nothing responds until 0x0a has been written to register 0x0a.
+    enabled: bool,
+    register: u8,
+    registers: [u8; 0x10],
+    output: u8,
+
+    memory: Vec<u8>,
+    clock: Clock,
+}
+
+impl TAMA5 {
+    pub fn new(cart: cart::Cartridge) -> Result<Self, cart::Error> {
+        let mut memory = load_save(&cart)?;
+        let clock = Clock::with_save(memory.get(MEMORY_SIZE..).unwrap_or(&[]));
+        memory.resize(MEMORY_SIZE, 0);
+        Ok(Self {
+            cart,
+            rom_bank: 0,
+
+            enabled: false,
+            register: 0,
+            registers: [0; 0x10],
+            output: 0,
+
+            memory,
+            clock,
+        })
+    }
+
+    // Writing the low address nibble runs the command selected in register 6.
+    fn execute(&mut self) {
+        let address = (((self.registers[0x06] & 0x01) << 4) | self.registers[0x07]) as usize;
+        let data = (self.registers[0x05] << 4) | self.registers[0x04];
+        match self.registers[0x06] >> 1 {
+            0x00 => self.memory[address] = data,
+            0x01 => self.output = self.memory[address],
+            0x02 => self.clock.write(address as u8 & 0x0f, data & 0x0f),
+            0x03 => self.output = self.clock.read(address as u8 & 0x0f),
+            _ => {}
+        }
+    }
+}
+
+impl MBC for TAMA5 {
+    fn read(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3fff => read_rom(&self.cart, 0, address),
+            0x4000..=0x7fff => read_rom(&self.cart, self.rom_bank, address),
+            0xa000 => match self.register {
+                0x0a => 0xf1,
+                0x0c if self.enabled => 0xf0 | (self.output & 0x0f),
+                0x0d if self.enabled => 0xf0 | (self.output >> 4),
+                _ => 0xff,
+            },
+            0xa001..=0xbfff => 0xff,
+            _ => unreachable!(),
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x7fff => {}
+            0xa000 => {
+                let value = value & 0x0f;
+                if self.register == 0x0a {
+                    self.enabled = value == 0x0a;
+                    return;
+                }
+                if !self.enabled {
+                    return;
+                }
+                self.registers[self.register as usize] = value;
+                match self.register {
+                    0x00 | 0x01 => {
+                        self.rom_bank =
+                            (((self.registers[0x01] & 0x01) << 4) | self.registers[0x00]) as usize
+                    }
+                    0x07 => self.execute(),
+                    _ => {}
+                }
+            }
+            0xa001 => self.register = value & 0x0f,
+            0xa002..=0xbfff => {}
+            _ => unreachable!(),
+        }
+    }
+
+    fn advance(&mut self, timing: Timing) {
+        self.clock.advance(timing);
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        Some([&self.memory[..], &self.clock.save()].concat())
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.memory
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.memory
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        save_state(self)
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), state::Error> {
+        load_state(self, state, |mbc| &mut mbc.cart)
+    }
+}
+
+// A TC8521 style clock, keeping the date and time as BCD digits in registers 0x0 to 0xc.
+#[derive(Serialize, Deserialize)]
+struct Clock {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    weekday: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+    cycles: u32,
+}
+
+impl Clock {
+    fn new() -> Self {
+        Self {
+            seconds: 0,
+            minutes: 0,
+            hours: 0,
+            weekday: 0,
+            day: 1,
+            month: 1,
+            year: 0,
+            cycles: 0,
+        }
+    }
+
+    fn with_save(data: &[u8]) -> Self {
+        let mut clock = Self::new();
+        if data.len() < CLOCK_SAVE_SIZE {
+            return clock;
+        }
+        // Footers written by other emulators may hold values the registers could never reach.
+        clock.seconds = data[0] % 60;
+        clock.minutes = data[1] % 60;
+        clock.hours = data[2] % 24;
+        clock.weekday = data[3] % 7;
+        clock.month = data[5].clamp(1, 12);
+        clock.year = data[6] % 100;
+        clock.day = data[4].clamp(1, clock.days_in_month());
+        let saved = u64::from_le_bytes(data[8..16].try_into().unwrap());
+        clock.advance_seconds(unix_time().saturating_sub(saved));
+        clock
+    }
+
+    fn save(&self) -> Vec<u8> {
+        let mut data = vec![
+            self.seconds,
+            self.minutes,
+            self.hours,
+            self.weekday,
+            self.day,
+            self.month,
+            self.year,
+            0,
+        ];
+        data.extend_from_slice(&unix_time().to_le_bytes());
+        data
+    }
+
+    fn advance(&mut self, timing: Timing) {
+        self.cycles += timing as u32;
+        while self.cycles >= CYCLES_PER_SECOND {
+            self.cycles -= CYCLES_PER_SECOND;
+            self.tick();
+        }
+    }
+
+    fn tick(&mut self) {
+        self.advance_seconds(1);
+    }
+
+    fn advance_seconds(&mut self, seconds: u64) {
+        let seconds = self.seconds as u64 + seconds;
+        self.seconds = (seconds % 60) as u8;
+        let minutes = self.minutes as u64 + seconds / 60;
+        self.minutes = (minutes % 60) as u8;
+        let hours = self.hours as u64 + minutes / 60;
+        self.hours = (hours % 24) as u8;
+        let days = hours / 24;
+        self.weekday = ((self.weekday as u64 + days) % 7) as u8;
+
+        // The calendar repeats every hundred years, with a leap year every four.
+        let mut days = days % (100 * 365 + 25);
+        while days > 0 {
+            let left = self.days_in_month().saturating_sub(self.day) as u64;
+            if days <= left {
+                self.day += days as u8;
+                return;
+            }
+            days -= left + 1;
+            self.day = 1;
+            self.month += 1;
+            if self.month > 12 {
+                self.month = 1;
+                self.year = (self.year + 1) % 100;
+            }
+        }
+    }
+
+    fn days_in_month(&self) -> u8 {
+        match self.month {
+            2 if self.year.is_multiple_of(4) => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        }
+    }
+
+    fn read(&self, register: u8) -> u8 {
+        match register {
+            0x00 => self.seconds % 10,
+            0x01 => self.seconds / 10,
+            0x02 => self.minutes % 10,
+            0x03 => self.minutes / 10,
+            0x04 => self.hours % 10,
+            0x05 => self.hours / 10,
+            0x06 => self.weekday,
+            0x07 => self.day % 10,
+            0x08 => self.day / 10,
+            0x09 => self.month % 10,
+            0x0a => self.month / 10,
+            0x0b => self.year % 10,
+            0x0c => self.year / 10,
+            _ => 0x00,
+        }
+    }
+
+    fn write(&mut self, register: u8, value: u8) {
+        let digits = |current: u8, value: u8, tens: bool| {
+            if tens {
+                current % 10 + value * 10
+            } else {
+                current / 10 * 10 + value % 10
+            }
+        };
+        match register {
+            0x00 | 0x01 => self.seconds = digits(self.seconds, value, register & 1 != 0) % 60,
+            0x02 | 0x03 => self.minutes = digits(self.minutes, value, register & 1 != 0) % 60,
+            0x04 | 0x05 => self.hours = digits(self.hours, value, register & 1 != 0) % 24,
+            0x06 => self.weekday = value % 7,
+            0x07 | 0x08 => self.day = digits(self.day, value, register == 0x08).clamp(1, 31),
+            0x09 | 0x0a => self.month = digits(self.month, value, register == 0x0a).clamp(1, 12),
+            0x0b | 0x0c => self.year = digits(self.year, value, register == 0x0c) % 100,
+            _ => {}
+        }
+        if register == 0x00 {
+            self.cycles = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mbc::test::rom;
+
+    fn write_register(mbc: &mut TAMA5, register: u8, value: u8) {
+        mbc.write(0xa001, register);
+        mbc.write(0xa000, value);
+    }
+
+    fn read_output(mbc: &mut TAMA5) -> u8 {
+        mbc.write(0xa001, 0x0c);
+        let low = mbc.read(0xa000) & 0x0f;
+        mbc.write(0xa001, 0x0d);
+        (mbc.read(0xa000) << 4) | low
+    }
+
+    #[test]
+    fn registers_and_memory() {
+        let mut mbc = TAMA5::new(rom(0xfd, 32, 0x00)).unwrap();
+        write_register(&mut mbc, 0x00, 0x05);
+        assert_eq!(mbc.read(0x4000), 0x00);
+
+        write_register(&mut mbc, 0x0a, 0x0a);
+        assert_eq!(mbc.read(0xa000), 0xf1);
+        write_register(&mut mbc, 0x00, 0x05);
+        write_register(&mut mbc, 0x01, 0x01);
+        assert_eq!(mbc.read(0x4000), 0x15);
+
+        write_register(&mut mbc, 0x04, 0x0c);
+        write_register(&mut mbc, 0x05, 0x0a);
+        write_register(&mut mbc, 0x06, 0x01);
+        write_register(&mut mbc, 0x07, 0x03);
+        write_register(&mut mbc, 0x06, 0x03);
+        write_register(&mut mbc, 0x07, 0x03);
+        assert_eq!(read_output(&mut mbc), 0xac);
+        assert_eq!(mbc.memory[0x13], 0xac);
+
+        write_register(&mut mbc, 0x04, 0x02);
+        write_register(&mut mbc, 0x06, 0x04);
+        write_register(&mut mbc, 0x07, 0x05);
+        for _ in 0..(CYCLES_PER_SECOND / 0x8000 * 3600) {
+            mbc.advance(0x8000);
+        }
+        write_register(&mut mbc, 0x06, 0x06);
+        write_register(&mut mbc, 0x07, 0x04);
+        assert_eq!(read_output(&mut mbc) & 0x0f, 0x01);
+        write_register(&mut mbc, 0x07, 0x05);
+        assert_eq!(read_output(&mut mbc) & 0x0f, 0x02);
+    }
+
+    #[test]
+    fn clock_catches_up() {
+        let mut clock = Clock::with_save(&[0xff; CLOCK_SAVE_SIZE]);
+        assert_eq!(
+            (clock.seconds, clock.minutes, clock.hours, clock.weekday),
+            (15, 15, 15, 3)
+        );
+        assert_eq!((clock.day, clock.month, clock.year), (31, 12, 55));
+
+        clock = Clock::new();
+        clock.advance_seconds(60 * 86400 + 3661);
+        assert_eq!((clock.seconds, clock.minutes, clock.hours), (1, 1, 1));
+        assert_eq!(
+            (clock.weekday, clock.day, clock.month, clock.year),
+            (4, 1, 3, 0)
+        );
+        clock.advance_seconds(100 * (365 * 86400) + 25 * 86400);
+        assert_eq!((clock.day, clock.month, clock.year), (1, 3, 0));
+    }
+}
@@ -1,8 +1,17 @@
+use crate::cart;
 use crate::cart::{CGBType, Cartridge, Controller, GBType};
+use crate::cheats::Cheats;
 use crate::mbc;
 use crate::mbc::MBC;
+use crate::save::SaveFile;
+use crate::state;
+use crate::timer::Timing;
+use serde::{Deserialize, Serialize};
 use std::{fs::File, io::Read, path::Path};
 
+// Battery saves are flushed to disk at most once per emulated second.
+const FLUSH_INTERVAL: u32 = 4_194_304;
+
 pub struct Memory {
     state: State,
     work_ram: Vec<u8>,
@@ -12,6 +21,11 @@ pub struct Memory {
     work_ram_bank: usize,
 
     gb: GBType,
+
+    save: Option<SaveFile>,
+    save_cycles: u32,
+
+    cheats: Cheats,
 }
 
 impl Memory {
@@ -25,28 +39,44 @@ impl Memory {
             work_ram: vec![0; 0x2000],
 
             gb: GBType::CGB(CGBType::SupportCGB),
+
+            save: None,
+            save_cycles: 0,
+
+            cheats: Cheats::new(),
         }
     }
 
-    pub fn with_cartridge(cart: Cartridge) -> Self {
+    pub fn with_cartridge(cart: Cartridge) -> Result<Self, cart::Error> {
         let mut mem = Self::new();
         mem.gb = cart.gb();
         if let GBType::CGB(_) = mem.gb {
             mem.work_ram = vec![0; 0x8000];
         }
+        let savepath = mbc::savepath(&cart);
         mem.state = State::MBC(match cart.cart_type().controller {
-            Controller::None => Box::new(mbc::None::new(cart)),
-            Controller::MBC1 => Box::new(mbc::MBC1::new(cart)),
-            Controller::MBC2 => Box::new(mbc::MBC2::new(cart)),
-            Controller::MBC3 => Box::new(mbc::MBC3::new(cart)),
-            Controller::MBC5 => Box::new(mbc::MBC5::new(cart)),
-            _ => panic!("unsupprted mbc"),
+            Controller::None => Box::new(mbc::None::new(cart)?),
+            Controller::MBC1 => Box::new(mbc::MBC1::new(cart)?),
+            Controller::MBC2 => Box::new(mbc::MBC2::new(cart)?),
+            Controller::MMM01 => Box::new(mbc::MMM01::new(cart)?),
+            Controller::MBC3 => Box::new(mbc::MBC3::new(cart)?),
+            Controller::MBC5 => Box::new(mbc::MBC5::new(cart)?),
+            Controller::MBC6 => Box::new(mbc::MBC6::new(cart)?),
+            Controller::MBC7 => Box::new(mbc::MBC7::new(cart)?),
+            Controller::HuC1 => Box::new(mbc::HuC1::new(cart)?),
+            Controller::HuC3 => Box::new(mbc::HuC3::new(cart)?),
+            Controller::Camera => Box::new(mbc::Camera::new(cart)?),
+            Controller::TAMA5 => Box::new(mbc::TAMA5::new(cart)?),
+            c => return Err(cart::Error::UnsupportedMapper(c)),
+        });
+        mem.save = savepath.map(|path| {
+            SaveFile::new(&path).with_loaded(mem.state.save_data().unwrap_or_default())
         });
-        mem
+        Ok(mem)
     }
 
     pub fn with_bootrom(mut self, data: &[u8]) -> Self {
-        self.state = match self.state {
+        self.state = match std::mem::take(&mut self.state) {
             State::MBC(m) => State::Boot(mbc::Boot::with_mbc(data, m)),
             State::None => State::Boot(mbc::Boot::with_data(data)),
             _ => panic!("already initialized with bootrom"),
@@ -61,13 +91,65 @@ impl Memory {
         Ok(self.with_bootrom(&data))
     }
 
+    // Feeds the given images to the sensor of a camera cartridge, one per captured picture.
+    pub fn with_camera_pictures(mut self, paths: &[&Path]) -> Result<Self, image::ImageError> {
+        let pictures = paths
+            .iter()
+            .map(|path| mbc::load_picture(path))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.state.pictures(pictures);
+        Ok(self)
+    }
+
+    // Leaves the battery save on disk alone, for runs that must not change it such as movie
+    // playback.
+    pub fn without_save(mut self) -> Self {
+        self.save = None;
+        self
+    }
+
+    pub fn with_cheats(mut self, cheats: Cheats) -> Self {
+        self.cheats = cheats;
+        self
+    }
+
+    pub fn cheats(&mut self) -> &mut Cheats {
+        &mut self.cheats
+    }
+
+    // Applies the GameShark codes, which the cheat devices did once every frame.
+    pub fn apply_cheats(&mut self) {
+        let writes = self.cheats.writes().collect::<Vec<_>>();
+        for (bank, address, value) in writes {
+            // Going around the mapper keeps the codes from switching banks or marking the save as
+            // changed every frame.
+            let index = match (bank, address) {
+                (_, 0xa000..=0xbfff) => {
+                    let ram = self.state.ram_mut();
+                    if let Some(i) = mbc::ram_address(ram, (bank & 0x0f) as usize, address) {
+                        ram[i] = value;
+                    }
+                    continue;
+                }
+                (_, 0xc000..=0xcfff) => address as usize - 0xc000,
+                (0x90..=0x97, _) if self.work_ram.len() > 0x2000 => {
+                    let bank = ((bank & 0x07) as usize).max(1);
+                    bank * 0x1000 + (address as usize - 0xd000)
+                }
+                _ => self.work_ram_bank * 0x1000 + (address as usize - 0xd000),
+            };
+            self.work_ram[index] = value;
+        }
+    }
+
     pub fn gb(&self) -> GBType {
         self.gb
     }
 
     pub fn read(&self, address: u16) -> u8 {
         match address {
-            0x0000..=0xbfff => self.state.read(address),
+            0x0000..=0x7fff => self.cheats.read(address, self.state.read(address)),
+            0x8000..=0xbfff => self.state.read(address),
             0xc000..=0xcfff => self.work_ram[address as usize - 0xc000],
             0xd000..=0xdfff => {
                 self.work_ram[(self.work_ram_bank * 0x1000) + (address as usize - 0xd000)]
@@ -89,7 +171,13 @@ impl Memory {
 
     pub fn write(&mut self, address: u16, value: u8) {
         match address {
-            0x0000..=0xbfff => self.state.write(address, value),
+            0x0000..=0x7fff => self.state.write(address, value),
+            0xa000..=0xbfff => {
+                self.state.write(address, value);
+                if let Some(save) = self.save.as_mut() {
+                    save.mark_dirty();
+                }
+            }
             0xc000..=0xcfff => self.work_ram[address as usize - 0xc000] = value,
             0xd000..=0xdfff => {
                 self.work_ram[(self.work_ram_bank * 0x1000) + (address as usize - 0xd000)] = value
@@ -113,6 +201,92 @@ impl Memory {
         }
     }
 
+    pub fn advance(&mut self, timing: Timing) {
+        self.state.advance(timing);
+        if self.state.save_changed() {
+            if let Some(save) = self.save.as_mut() {
+                save.mark_dirty();
+            }
+        }
+        self.save_cycles += timing as u32;
+        if self.save_cycles >= FLUSH_INTERVAL {
+            self.save_cycles -= FLUSH_INTERVAL;
+            self.flush();
+        }
+    }
+
+    // Writes the battery backed cartridge state to disk if it changed since the last flush.
+    pub fn flush(&mut self) {
+        let save = match self.save.as_mut() {
+            Some(save) if save.is_dirty() => save,
+            _ => return,
+        };
+        if let Some(data) = self.state.save_data() {
+            if let Err(e) = save.write(&data) {
+                eprintln!("warning: unable to write save file: {}", e);
+            }
+        }
+    }
+
+    pub fn work_ram(&self) -> &[u8] {
+        &self.work_ram
+    }
+
+    pub fn high_ram(&self) -> &[u8] {
+        &self.high_ram
+    }
+
+    pub fn cart_ram(&self) -> &[u8] {
+        self.state.ram()
+    }
+
+    pub fn rumble(&self) -> bool {
+        self.state.rumble()
+    }
+
+    pub fn tilt(&mut self, x: f32, y: f32) {
+        self.state.tilt(x, y)
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        state::to_bytes(&MemoryState {
+            work_ram: self.work_ram.clone(),
+            high_ram: self.high_ram.to_vec(),
+            io: self.io.to_vec(),
+            work_ram_bank: self.work_ram_bank,
+            booting: self.has_bootrom(),
+            mbc: self.state.save_state(),
+        })
+    }
+
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), state::Error> {
+        let loaded: MemoryState = state::from_bytes(data)?;
+        if loaded.booting && !self.has_bootrom() {
+            return Err(state::Error::Booting);
+        }
+        // Work ram banks past the first are only there on the Color Game Boy.
+        let banks = self.work_ram.len() / 0x1000;
+        if loaded.work_ram.len() != self.work_ram.len()
+            || loaded.high_ram.len() != self.high_ram.len()
+            || loaded.io.len() != self.io.len()
+            || !(1..banks).contains(&loaded.work_ram_bank)
+        {
+            return Err(state::Error::Mismatch);
+        }
+        self.state.load_state(&loaded.mbc)?;
+        if !loaded.booting {
+            self.disable_booting();
+        }
+        self.work_ram = loaded.work_ram;
+        self.high_ram.copy_from_slice(&loaded.high_ram);
+        self.io.copy_from_slice(&loaded.io);
+        self.work_ram_bank = loaded.work_ram_bank;
+        if let Some(save) = self.save.as_mut() {
+            save.mark_dirty();
+        }
+        Ok(())
+    }
+
     pub fn has_bootrom(&self) -> bool {
         match self.state {
             State::Boot(_) => true,
@@ -129,6 +303,28 @@ impl Memory {
     }
 }
 
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Memory {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct MemoryState {
+    work_ram: Vec<u8>,
+    high_ram: Vec<u8>,
+    io: Vec<u8>,
+    work_ram_bank: usize,
+    booting: bool,
+    mbc: Vec<u8>,
+}
+
 enum State {
     None,
     Boot(mbc::Boot),
@@ -157,4 +353,149 @@ impl mbc::MBC for State {
             _ => panic!("write into invalid MBC state"),
         }
     }
+
+    fn advance(&mut self, timing: Timing) {
+        match self {
+            State::MBC(m) => m.advance(timing),
+            State::Boot(b) => b.advance(timing),
+            _ => {}
+        }
+    }
+
+    fn rumble(&self) -> bool {
+        match self {
+            State::MBC(m) => m.rumble(),
+            State::Boot(b) => b.rumble(),
+            _ => false,
+        }
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        match self {
+            State::MBC(m) => m.save_data(),
+            State::Boot(b) => b.save_data(),
+            _ => None,
+        }
+    }
+
+    fn save_changed(&mut self) -> bool {
+        match self {
+            State::MBC(m) => m.save_changed(),
+            State::Boot(b) => b.save_changed(),
+            _ => false,
+        }
+    }
+
+    fn ram(&self) -> &[u8] {
+        match self {
+            State::MBC(m) => m.ram(),
+            State::Boot(b) => b.ram(),
+            _ => &[],
+        }
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        match self {
+            State::MBC(m) => m.ram_mut(),
+            State::Boot(b) => b.ram_mut(),
+            _ => &mut [],
+        }
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        match self {
+            State::MBC(m) => m.save_state(),
+            State::Boot(b) => b.save_state(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), state::Error> {
+        match self {
+            State::MBC(m) => m.load_state(state),
+            State::Boot(b) => b.load_state(state),
+            _ => Ok(()),
+        }
+    }
+
+    fn tilt(&mut self, x: f32, y: f32) {
+        match self {
+            State::MBC(m) => m.tilt(x, y),
+            State::Boot(b) => b.tilt(x, y),
+            _ => {}
+        }
+    }
+
+    fn pictures(&mut self, pictures: Vec<Vec<u8>>) {
+        match self {
+            State::MBC(m) => m.pictures(pictures),
+            State::Boot(b) => b.pictures(pictures),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn flushes_programmed_flash() {
+        let dir = std::env::temp_dir().join(format!("geebee-flash-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut data = vec![0; 0x20000];
+        data[0x0147] = 0x20;
+        data[0x0148] = 0x02;
+        data[0x0149] = 0x03;
+        fs::write(dir.join("game.gb"), &data).unwrap();
+        let cart = Cartridge::new()
+            .with_lenient(true)
+            .with_path(&dir.join("game.gb"))
+            .unwrap();
+
+        let mut mem = Memory::with_cartridge(cart).unwrap();
+        mem.write(0x0c00, 0x01);
+        mem.write(0x1000, 0x01);
+        mem.write(0x2800, 0x08);
+        for &(bank, address, value) in &[
+            (0x02, 0x5555, 0xaa),
+            (0x01, 0x2aaa, 0x55),
+            (0x02, 0x5555, 0xa0),
+            (0x00, 0x0000, 0x5a),
+        ] {
+            mem.write(0x2000, bank);
+            mem.write(0x4000 | (address & 0x1fff), value);
+        }
+        mem.advance(4);
+        mem.flush();
+        let save = fs::read(dir.join("game.sav")).unwrap();
+        assert_eq!(save[0x8000], 0x5a);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_mismatched_states() {
+        let mut mem = Memory::new();
+        let state = |work_ram: usize, work_ram_bank: usize| {
+            state::to_bytes(&MemoryState {
+                work_ram: vec![0; work_ram],
+                high_ram: vec![0; 0x7f],
+                io: vec![0; 0x80],
+                work_ram_bank,
+                booting: false,
+                mbc: Vec::new(),
+            })
+        };
+        assert!(mem.load_state(&state(0x2000, 1)).is_ok());
+        assert!(matches!(
+            mem.load_state(&state(0x8000, 1)),
+            Err(state::Error::Mismatch)
+        ));
+        assert!(matches!(
+            mem.load_state(&state(0x2000, 3)),
+            Err(state::Error::Mismatch)
+        ));
+    }
 }
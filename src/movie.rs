@@ -0,0 +1,196 @@
+use crate::joypad::Joypad;
+use crate::state;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+use thiserror::Error;
+
+const MAGIC: &[u8] = b"GBMV";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Start {
+    PowerOn,
+    SaveState,
+}
+
+// The joypad and tilt of every frame, starting from a captured machine state. Even power-on
+// movies keep the state right after power-on, so the battery save and clock on disk do not
+// change how they play back.
+#[derive(Serialize, Deserialize)]
+pub struct Movie {
+    rom_crc: u32,
+    start: Start,
+    state: Vec<u8>,
+    frames: Vec<Frame>,
+    // The hash of the screen after the last frame, to check playback against.
+    screen_crc: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct Frame {
+    buttons: u8,
+    tilt: (f32, f32),
+}
+
+impl Movie {
+    pub fn new(rom_crc: u32, start: Start, state: Vec<u8>) -> Self {
+        Self {
+            rom_crc,
+            start,
+            state,
+            frames: Vec::new(),
+            screen_crc: None,
+        }
+    }
+
+    pub fn with_path(path: &Path) -> Result<Self, Error> {
+        let data = fs::read(path)?;
+        if !data.starts_with(MAGIC) {
+            return Err(Error::NotAMovie);
+        }
+        Ok(state::from_bytes(&data[MAGIC.len()..])?)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), Error> {
+        fs::write(path, [MAGIC, &state::to_bytes(self)].concat())?;
+        Ok(())
+    }
+
+    pub fn check_rom(&self, rom_crc: u32) -> Result<(), Error> {
+        if self.rom_crc != rom_crc {
+            return Err(Error::WrongRom);
+        }
+        Ok(())
+    }
+
+    pub fn start(&self) -> Start {
+        self.start
+    }
+
+    pub fn state(&self) -> &[u8] {
+        &self.state
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn screen_crc(&self) -> Option<u32> {
+        self.screen_crc
+    }
+
+    // Records the screen the movie ends on.
+    pub fn finish(&mut self, screen: &[u8]) {
+        self.screen_crc = Some(screen_crc(screen));
+    }
+}
+
+pub fn screen_crc(screen: &[u8]) -> u32 {
+    crc32fast::hash(screen)
+}
+
+// Records the joypad and tilt into a movie, or plays them back, once at the start of every
+// frame.
+pub struct Session {
+    movie: Movie,
+    recording: bool,
+    frame: usize,
+}
+
+impl Session {
+    pub fn record(movie: Movie) -> Self {
+        Self {
+            movie,
+            recording: true,
+            frame: 0,
+        }
+    }
+
+    pub fn play(movie: Movie) -> Self {
+        Self {
+            movie,
+            recording: false,
+            frame: 0,
+        }
+    }
+
+    // Whether the joypad is still driven by the movie, rather than by the player.
+    pub fn is_playing(&self) -> bool {
+        !self.recording && self.frame < self.movie.len()
+    }
+
+    pub fn frame(&mut self, joypad: &mut Joypad) {
+        if self.recording {
+            self.movie.frames.push(Frame {
+                buttons: joypad.buttons(),
+                tilt: joypad.tilt_position(),
+            });
+        } else if let Some(frame) = self.movie.frames.get(self.frame) {
+            joypad.set_buttons(frame.buttons);
+            joypad.tilt(frame.tilt.0, frame.tilt.1);
+        }
+        self.frame += 1;
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    pub fn into_movie(self) -> Movie {
+        self.movie
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("not a movie")]
+    NotAMovie,
+    #[error("movie was recorded with a different rom")]
+    WrongRom,
+    #[error("invalid movie: {source}")]
+    State {
+        #[from]
+        source: state::Error,
+    },
+    #[error("io error: {source}")]
+    Io {
+        #[from]
+        source: io::Error,
+    },
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::joypad::Button;
+
+    #[test]
+    fn records_and_plays_back() {
+        let mut joypad = Joypad::new();
+        let mut session = Session::record(Movie::new(0, Start::PowerOn, Vec::new()));
+        joypad.press(Button::A);
+        session.frame(&mut joypad);
+        joypad.release(Button::A);
+        joypad.press(Button::Left);
+        joypad.tilt(0.5, -2.0);
+        session.frame(&mut joypad);
+        let movie = session.into_movie();
+        assert_eq!(movie.frames.len(), 2);
+        assert_eq!(movie.frames[1].tilt, (0.5, -1.0));
+
+        let mut joypad = Joypad::new();
+        let mut session = Session::play(movie);
+        session.frame(&mut joypad);
+        assert_eq!(joypad.buttons(), 0x10);
+        assert!(session.is_playing());
+        session.frame(&mut joypad);
+        assert_eq!(joypad.buttons(), 0x04);
+        assert_eq!(joypad.check_tilt(), Some((0.5, -1.0)));
+        assert!(!session.is_playing());
+    }
+}
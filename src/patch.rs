@@ -0,0 +1,335 @@
+use std::convert::TryFrom;
+use thiserror::Error;
+
+// The largest cartridges hold 8 MiB of rom, so patches asking for more are corrupt.
+const MAX_TARGET_SIZE: usize = 0x80_0000;
+
+// Applies an IPS, UPS or BPS patch to a rom, detecting the format from the patch header.
+pub fn apply(patch: &[u8], rom: &[u8]) -> Result<Vec<u8>, Error> {
+    if patch.starts_with(b"PATCH") {
+        apply_ips(patch, rom)
+    } else if patch.starts_with(b"UPS1") {
+        apply_ups(patch, rom)
+    } else if patch.starts_with(b"BPS1") {
+        apply_bps(patch, rom)
+    } else {
+        Err(Error::UnknownFormat)
+    }
+}
+
+fn apply_ips(patch: &[u8], rom: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut target = rom.to_vec();
+    let mut reader = Reader::new(patch, 5);
+    loop {
+        let offset = reader.bytes(3)?;
+        if offset == b"EOF" {
+            break;
+        }
+        let offset = big_endian(offset);
+        let size = big_endian(reader.bytes(2)?);
+        // A record without a size is run length encoded.
+        let data = if size == 0 {
+            let count = big_endian(reader.bytes(2)?);
+            vec![reader.byte()?; count]
+        } else {
+            reader.bytes(size)?.to_vec()
+        };
+        if target.len() < offset + data.len() {
+            target.resize(offset + data.len(), 0);
+        }
+        target[offset..offset + data.len()].copy_from_slice(&data);
+    }
+    // Some patches truncate the rom with a length following the end marker.
+    if let Ok(size) = reader.bytes(3) {
+        target.truncate(big_endian(size));
+    }
+    Ok(target)
+}
+
+fn big_endian(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0, |x, &b| (x << 8) | b as usize)
+}
+
+fn apply_ups(patch: &[u8], rom: &[u8]) -> Result<Vec<u8>, Error> {
+    let (end, source_crc, target_crc) = verify_footer(patch)?;
+    if crc32fast::hash(rom) != source_crc {
+        return Err(Error::SourceChecksum);
+    }
+    let mut reader = Reader::new(&patch[..end], 4);
+    let _source_size = reader.varint()?;
+    let target_size = reader.varint()?;
+    if target_size > MAX_TARGET_SIZE {
+        return Err(Error::TooLarge);
+    }
+
+    let mut target = rom.to_vec();
+    target.resize(target_size, 0);
+    let mut output = 0usize;
+    while !reader.done() {
+        output = output
+            .checked_add(reader.varint()?)
+            .ok_or(Error::InvalidPatch)?;
+        // Each hunk xors the rom until a zero byte, which still skips over a byte itself.
+        loop {
+            let x = reader.byte()?;
+            if let Some(b) = target.get_mut(output) {
+                *b ^= x;
+            }
+            output += 1;
+            if x == 0 {
+                break;
+            }
+        }
+    }
+    if crc32fast::hash(&target) != target_crc {
+        return Err(Error::TargetChecksum);
+    }
+    Ok(target)
+}
+
+fn apply_bps(patch: &[u8], rom: &[u8]) -> Result<Vec<u8>, Error> {
+    let (end, source_crc, target_crc) = verify_footer(patch)?;
+    if crc32fast::hash(rom) != source_crc {
+        return Err(Error::SourceChecksum);
+    }
+    let mut reader = Reader::new(&patch[..end], 4);
+    let _source_size = reader.varint()?;
+    let target_size = reader.varint()?;
+    let metadata_size = reader.varint()?;
+    reader.bytes(metadata_size)?;
+    if target_size > MAX_TARGET_SIZE {
+        return Err(Error::TooLarge);
+    }
+
+    let mut target = Vec::with_capacity(target_size);
+    let mut source_offset = 0isize;
+    let mut target_offset = 0isize;
+    let relative = |reader: &mut Reader, offset: &mut isize| -> Result<(), Error> {
+        let data = reader.varint()?;
+        let value = isize::try_from(data >> 1).map_err(|_| Error::InvalidPatch)?;
+        *offset = if data & 1 != 0 {
+            offset.checked_sub(value)
+        } else {
+            offset.checked_add(value)
+        }
+        .ok_or(Error::InvalidPatch)?;
+        Ok(())
+    };
+    // Offsets that point before the start or past the end of the data mean the patch is corrupt.
+    let range = |offset: isize, length: usize| {
+        let start = usize::try_from(offset).map_err(|_| Error::InvalidPatch)?;
+        let end = start.checked_add(length).ok_or(Error::InvalidPatch)?;
+        Ok::<_, Error>(start..end)
+    };
+    while !reader.done() {
+        let data = reader.varint()?;
+        let length = (data >> 2) + 1;
+        if length > target_size - target.len() {
+            return Err(Error::InvalidPatch);
+        }
+        match data & 0x03 {
+            0 => {
+                let start = target.len() as isize;
+                let bytes = rom.get(range(start, length)?).ok_or(Error::InvalidPatch)?;
+                target.extend_from_slice(bytes);
+            }
+            1 => target.extend_from_slice(reader.bytes(length)?),
+            2 => {
+                relative(&mut reader, &mut source_offset)?;
+                let bytes = rom
+                    .get(range(source_offset, length)?)
+                    .ok_or(Error::InvalidPatch)?;
+                target.extend_from_slice(bytes);
+                source_offset += length as isize;
+            }
+            _ => {
+                relative(&mut reader, &mut target_offset)?;
+                // The copy may overlap with what it is writing, so it goes byte by byte.
+                for i in range(target_offset, length)? {
+                    let b = *target.get(i).ok_or(Error::InvalidPatch)?;
+                    target.push(b);
+                }
+                target_offset += length as isize;
+            }
+        }
+    }
+    if target.len() != target_size || crc32fast::hash(&target) != target_crc {
+        return Err(Error::TargetChecksum);
+    }
+    Ok(target)
+}
+
+// UPS and BPS patches end with the checksums of the source, the target and the patch itself.
+fn verify_footer(patch: &[u8]) -> Result<(usize, u32, u32), Error> {
+    if patch.len() < 16 {
+        return Err(Error::InvalidPatch);
+    }
+    let end = patch.len() - 12;
+    let word = |i: usize| {
+        let b = &patch[end + i * 4..end + i * 4 + 4];
+        u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+    };
+    if crc32fast::hash(&patch[..patch.len() - 4]) != word(2) {
+        return Err(Error::PatchChecksum);
+    }
+    Ok((end, word(0), word(1)))
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        Self { data, pos }
+    }
+
+    fn done(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn byte(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], Error> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + count)
+            .ok_or(Error::InvalidPatch)?;
+        self.pos += count;
+        Ok(bytes)
+    }
+
+    // Variable length numbers where every continuation also adds one, so that every value has
+    // exactly one encoding.
+    fn varint(&mut self) -> Result<usize, Error> {
+        let mut value = 0usize;
+        let mut shift = 1usize;
+        loop {
+            let x = self.byte()?;
+            value = ((x & 0x7f) as usize)
+                .checked_mul(shift)
+                .and_then(|digit| value.checked_add(digit))
+                .ok_or(Error::InvalidPatch)?;
+            if x & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift = shift.checked_mul(0x80).ok_or(Error::InvalidPatch)?;
+            value = value.checked_add(shift).ok_or(Error::InvalidPatch)?;
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("unknown patch format")]
+    UnknownFormat,
+    #[error("patch is truncated or corrupt")]
+    InvalidPatch,
+    #[error("patch checksum does not match")]
+    PatchChecksum,
+    #[error("rom does not match the one the patch was made for")]
+    SourceChecksum,
+    #[error("patched rom checksum does not match")]
+    TargetChecksum,
+    #[error("patched rom would be larger than any cartridge")]
+    TooLarge,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn with_footer(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+        patch.extend_from_slice(&crc32fast::hash(source).to_le_bytes());
+        patch.extend_from_slice(&crc32fast::hash(target).to_le_bytes());
+        let crc = crc32fast::hash(&patch);
+        patch.extend_from_slice(&crc.to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn ips() {
+        let mut patch = b"PATCH".to_vec();
+        patch.extend_from_slice(&[0x00, 0x00, 0x01, 0x00, 0x02, 0xaa, 0xbb]);
+        patch.extend_from_slice(&[0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x02, 0xcc]);
+        patch.extend_from_slice(b"EOF");
+        assert_eq!(
+            apply(&patch, &[0, 1, 2, 3]).unwrap(),
+            [0, 0xaa, 0xbb, 3, 0, 0xcc, 0xcc]
+        );
+    }
+
+    #[test]
+    fn ups() {
+        let source = [1, 2, 3, 4];
+        let target = [1, 7, 3, 4, 9];
+        let mut patch = b"UPS1".to_vec();
+        patch.extend_from_slice(&[0x84, 0x85, 0x81, 2 ^ 7, 0x00, 0x81, 9, 0x00]);
+        let patch = with_footer(patch, &source, &target);
+        assert_eq!(apply(&patch, &source).unwrap(), target);
+        assert!(matches!(
+            apply(&patch, &[1, 2, 3, 5]),
+            Err(Error::SourceChecksum)
+        ));
+
+        // A target far larger than any cartridge.
+        let patch = with_footer(
+            [&b"UPS1\x84"[..], &[0x7f; 8][..], &[0x80]].concat(),
+            &source,
+            &target,
+        );
+        assert!(matches!(apply(&patch, &source), Err(Error::TooLarge)));
+        // Hunks skipping past the end of the address space.
+        let skip = [&[0x7f; 8][..], &[0xff, 0x00]].concat();
+        let patch = with_footer(
+            [&b"UPS1\x84\x85"[..], &skip, &skip].concat(),
+            &source,
+            &target,
+        );
+        assert!(matches!(apply(&patch, &source), Err(Error::InvalidPatch)));
+    }
+
+    #[test]
+    fn bps() {
+        let source = [1, 2, 3, 4];
+        let target = [1, 2, 9, 9, 9, 3, 4];
+        let mut patch = b"BPS1".to_vec();
+        // Source read of 2, target read of 1, target copy of 2, source copy of 2 from offset 2.
+        patch.extend_from_slice(&[0x84, 0x87, 0x80]);
+        patch.extend_from_slice(&[0x84, 0x81, 9, 0x87, 0x84, 0x86, 0x84]);
+        let patch = with_footer(patch, &source, &target);
+        assert_eq!(apply(&patch, &source).unwrap(), target);
+    }
+
+    #[test]
+    fn malformed_bps() {
+        let source = [1, 2, 3, 4];
+        let header = [b'B', b'P', b'S', b'1', 0x84, 0x81, 0x80];
+        // A source copy from before the start of the rom.
+        let patch = with_footer([&header[..], &[0x82, 0x8b]].concat(), &source, &[1]);
+        assert!(matches!(apply(&patch, &source), Err(Error::InvalidPatch)));
+        // A target copy from before the start of the output.
+        let patch = with_footer([&header[..], &[0x83, 0x83]].concat(), &source, &[1]);
+        assert!(matches!(apply(&patch, &source), Err(Error::InvalidPatch)));
+        // A source copy from an offset too large to address.
+        let patch = with_footer(
+            [&header[..], &[0x82], &[0x7f; 10][..], &[0x81]].concat(),
+            &source,
+            &[1],
+        );
+        assert!(matches!(apply(&patch, &source), Err(Error::InvalidPatch)));
+        // A target far larger than any cartridge.
+        let patch = with_footer(
+            [&b"BPS1\x84"[..], &[0x7f; 8][..], &[0x80, 0x80, 0x80]].concat(),
+            &source,
+            &[1],
+        );
+        assert!(matches!(apply(&patch, &source), Err(Error::TooLarge)));
+        // A target copy running past the target size.
+        let patch = with_footer([&header[..], &[0x80, 0x87, 0x80]].concat(), &source, &[1]);
+        assert!(matches!(apply(&patch, &source), Err(Error::InvalidPatch)));
+    }
+}
@@ -0,0 +1,132 @@
+use crate::cpu::CPU;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+
+// Keeps machine states from every few frames to step back through. Only the newest state is
+// kept whole, every older one is stored as the compressed difference to the state after it, and
+// the oldest ones are dropped once the buffer goes over its memory budget.
+pub struct Rewind {
+    interval: usize,
+    budget: usize,
+    frame: usize,
+
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Delta>,
+    size: usize,
+}
+
+impl Rewind {
+    pub fn new(interval: usize, budget: usize) -> Self {
+        Self {
+            interval: interval.max(1),
+            budget,
+            frame: 0,
+
+            latest: None,
+            deltas: VecDeque::new(),
+            size: 0,
+        }
+    }
+
+    // Called after every emulated frame, taking a snapshot every interval frames.
+    pub fn frame(&mut self, cpu: &CPU) {
+        if self.budget == 0 {
+            return;
+        }
+        self.frame += 1;
+        if self.frame.is_multiple_of(self.interval) {
+            self.push(cpu.save_state());
+        }
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) {
+        if let Some(previous) = self.latest.take() {
+            let delta = Delta::new(&previous, &state);
+            self.size += delta.data.len();
+            self.deltas.push_back(delta);
+        }
+        while self.size + state.len() > self.budget {
+            match self.deltas.pop_front() {
+                Some(delta) => self.size -= delta.data.len(),
+                None => break,
+            }
+        }
+        self.latest = Some(state);
+    }
+
+    // Steps back to the snapshot before the newest one, returning the state to restore. Stops
+    // at the oldest snapshot still in the buffer.
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let delta = self.deltas.pop_back()?;
+        self.size -= delta.data.len();
+        let state = delta.apply(self.latest.as_ref()?);
+        self.latest = Some(state.clone());
+        Some(state)
+    }
+}
+
+struct Delta {
+    len: usize,
+    data: Vec<u8>,
+}
+
+impl Delta {
+    // Most of the machine does not change between snapshots, so xoring them leaves long runs of
+    // zeros that deflate well.
+    fn new(older: &[u8], newer: &[u8]) -> Self {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+        encoder
+            .write_all(&xor(older, newer))
+            .expect("writing into memory");
+        Self {
+            len: older.len(),
+            data: encoder.finish().expect("writing into memory"),
+        }
+    }
+
+    fn apply(&self, newer: &[u8]) -> Vec<u8> {
+        let mut diff = Vec::new();
+        DeflateDecoder::new(&self.data[..])
+            .read_to_end(&mut diff)
+            .expect("reading from memory");
+        let mut older = xor(&diff, newer);
+        older.truncate(self.len);
+        older
+    }
+}
+
+fn xor(a: &[u8], b: &[u8]) -> Vec<u8> {
+    (0..a.len().max(b.len()))
+        .map(|i| a.get(i).unwrap_or(&0) ^ b.get(i).unwrap_or(&0))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn steps_back_through_snapshots() {
+        let mut rewind = Rewind::new(1, 1 << 20);
+        rewind.push(vec![1; 100]);
+        rewind.push(vec![2; 120]);
+        rewind.push(vec![3; 80]);
+        assert_eq!(rewind.pop(), Some(vec![2; 120]));
+        assert_eq!(rewind.pop(), Some(vec![1; 100]));
+        assert_eq!(rewind.pop(), None);
+
+        rewind.push(vec![4; 100]);
+        assert_eq!(rewind.pop(), Some(vec![1; 100]));
+
+        // Only the newest state and a single delta fit.
+        let mut rewind = Rewind::new(1, 1024);
+        for i in 0..10 {
+            rewind.push(vec![i; 1000]);
+        }
+        assert_eq!(rewind.pop(), Some(vec![8; 1000]));
+        assert_eq!(rewind.pop(), None);
+    }
+}
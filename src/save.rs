@@ -0,0 +1,113 @@
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+const BACKUPS: usize = 3;
+
+// Keeps the battery backed data of a cartridge on disk. Writes go to a temporary file which
+// then replaces the save in a single rename, so an interrupted write never truncates it. The
+// first write of every session rotates the previous save into the numbered backups.
+pub struct SaveFile {
+    path: PathBuf,
+    dirty: bool,
+    rotated: bool,
+    written: Vec<u8>,
+}
+
+impl SaveFile {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            dirty: false,
+            rotated: false,
+            written: Vec::new(),
+        }
+    }
+
+    // The data as it was loaded, so an unchanged save is not written back.
+    pub fn with_loaded(mut self, data: Vec<u8>) -> Self {
+        self.written = data;
+        self
+    }
+
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.dirty = false;
+        if data == &self.written[..] {
+            return Ok(());
+        }
+        if !self.rotated && self.path.exists() {
+            self.rotate()?;
+        }
+        self.rotated = true;
+
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let temp = self.sibling(".tmp");
+        let mut f = File::create(&temp)?;
+        f.write_all(data)?;
+        f.sync_all()?;
+        fs::rename(&temp, &self.path)?;
+        self.written = data.to_vec();
+        Ok(())
+    }
+
+    fn rotate(&self) -> io::Result<()> {
+        for i in (1..BACKUPS).rev() {
+            let from = self.backup(i);
+            if from.exists() {
+                fs::rename(&from, self.backup(i + 1))?;
+            }
+        }
+        fs::copy(&self.path, self.backup(1))?;
+        Ok(())
+    }
+
+    fn backup(&self, i: usize) -> PathBuf {
+        self.sibling(&format!(".bak{}", i))
+    }
+
+    fn sibling(&self, suffix: &str) -> PathBuf {
+        let mut path = OsString::from(self.path.as_os_str());
+        path.push(suffix);
+        path.into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn writes_and_rotates_backups() {
+        let dir = std::env::temp_dir().join(format!("geebee-save-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("game.sav");
+        fs::write(&path, [1]).unwrap();
+        fs::write(dir.join("game.sav.bak1"), [0]).unwrap();
+
+        let mut save = SaveFile::new(&path).with_loaded(vec![1]);
+        save.mark_dirty();
+        save.write(&[1]).unwrap();
+        assert!(!save.is_dirty());
+        assert!(!dir.join("game.sav.bak2").exists());
+
+        save.write(&[2]).unwrap();
+        save.write(&[3]).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), [3]);
+        assert_eq!(fs::read(dir.join("game.sav.bak1")).unwrap(), [1]);
+        assert_eq!(fs::read(dir.join("game.sav.bak2")).unwrap(), [0]);
+        assert!(!dir.join("game.sav.tmp").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,116 @@
+use crate::joypad::{Button, Joypad, Macro};
+use std::fs;
+use std::io;
+use std::path::Path;
+use thiserror::Error;
+
+// Joypad input for headless runs, as lines of the frame to act on followed by `press`, `release`
+// or `turbo` and a button, or `macro` and either the steps of a macro or the index of one from
+// the config. Blank lines and lines starting with `#` are skipped.
+pub struct Script {
+    events: Vec<(usize, Command)>,
+    next: usize,
+}
+
+enum Command {
+    Press(Button),
+    Release(Button),
+    Turbo(Button),
+    Macro(Macro),
+}
+
+impl Script {
+    pub fn with_path(path: &Path, macros: &[Macro]) -> Result<Self, Error> {
+        Self::parse(&fs::read_to_string(path)?, macros)
+    }
+
+    pub fn parse(s: &str, macros: &[Macro]) -> Result<Self, Error> {
+        let mut events = Vec::new();
+        for (i, line) in s.lines().enumerate() {
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            if words.is_empty() || words[0].starts_with('#') {
+                continue;
+            }
+            let invalid = |message: String| Error::Line {
+                line: i + 1,
+                message,
+            };
+            let frame = words[0]
+                .parse()
+                .map_err(|_| invalid(format!("invalid frame {}", words[0])))?;
+            let command = match &words[1..] {
+                ["press", button] => Command::Press(button.parse().map_err(invalid)?),
+                ["release", button] => Command::Release(button.parse().map_err(invalid)?),
+                ["turbo", button] => Command::Turbo(button.parse().map_err(invalid)?),
+                ["macro", steps @ ..] if !steps.is_empty() => {
+                    Command::Macro(match steps[0].parse::<usize>() {
+                        Ok(index) if steps.len() == 1 => macros
+                            .get(index)
+                            .cloned()
+                            .ok_or_else(|| invalid(format!("no macro {} in the config", index)))?,
+                        _ => steps.join(" ").parse().map_err(invalid)?,
+                    })
+                }
+                _ => return Err(invalid(format!("unknown command {}", words[1..].join(" ")))),
+            };
+            events.push((frame, command));
+        }
+        // Commands for the same frame keep the order they were written in.
+        events.sort_by_key(|&(frame, _)| frame);
+        Ok(Self { events, next: 0 })
+    }
+
+    // Called before every frame runs, with the number of frames run so far.
+    pub fn frame(&mut self, frame: usize, joypad: &mut Joypad) {
+        while let Some((_, command)) = self.events.get(self.next).filter(|e| e.0 <= frame) {
+            match command {
+                Command::Press(button) => joypad.press(*button),
+                Command::Release(button) => {
+                    joypad.release(*button);
+                    joypad.release_turbo(*button);
+                }
+                Command::Turbo(button) => joypad.press_turbo(*button),
+                Command::Macro(steps) => joypad.play_macro(steps),
+            }
+            self.next += 1;
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("invalid input script on line {line}: {message}")]
+    Line { line: usize, message: String },
+    #[error("io error: {source}")]
+    Io {
+        #[from]
+        source: io::Error,
+    },
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn drives_the_joypad() {
+        let macros = ["b*2".parse().unwrap()];
+        let mut script = Script::parse(
+            "# start the game\n2 release a\n0 press a\n\n3 macro 0\n",
+            &macros,
+        )
+        .unwrap();
+        let mut joypad = Joypad::new();
+        let mut buttons = Vec::new();
+        for frame in 0..5 {
+            script.frame(frame, &mut joypad);
+            joypad.frame();
+            buttons.push(joypad.buttons());
+        }
+        assert_eq!(buttons, [0x10, 0x10, 0x00, 0x20, 0x20]);
+
+        assert!(Script::parse("0 macro 1\n", &macros).is_err());
+        assert!(Script::parse("0 press x\n", &macros).is_err());
+        assert!(Script::parse("soon press a\n", &macros).is_err());
+    }
+}
@@ -0,0 +1,192 @@
+use crate::memory::Memory;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Region {
+    Work,
+    High,
+    Cart,
+}
+
+const REGIONS: [Region; 3] = [Region::Work, Region::High, Region::Cart];
+
+impl Region {
+    fn data(self, memory: &Memory) -> &[u8] {
+        match self {
+            Region::Work => memory.work_ram(),
+            Region::High => memory.high_ram(),
+            Region::Cart => memory.cart_ram(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    Equal,
+    Changed,
+    Increased,
+    Decreased,
+    Value(i32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Candidate {
+    pub region: Region,
+    pub offset: usize,
+    pub value: i32,
+}
+
+// Shows where the game sees the value, along with the bank it has to be mapped into.
+impl fmt::Display for Candidate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.region {
+            Region::Work if self.offset < 0x1000 => write!(f, "{:04x}", 0xc000 + self.offset)?,
+            Region::Work => write!(
+                f,
+                "{:04x} (bank {})",
+                0xd000 + self.offset % 0x1000,
+                self.offset / 0x1000
+            )?,
+            Region::High => write!(f, "{:04x}", 0xff80 + self.offset)?,
+            Region::Cart => write!(
+                f,
+                "{:04x} (cart bank {})",
+                0xa000 + self.offset % 0x2000,
+                self.offset / 0x2000
+            )?,
+        }
+        write!(f, ": {}", self.value)
+    }
+}
+
+// Narrows down where a game keeps a value by comparing snapshots of its ram between frames.
+// Every filter keeps the candidates that match against the previous snapshot, then takes a new
+// one to compare the next filter against.
+pub struct Search {
+    word: bool,
+    signed: bool,
+    snapshot: Vec<Vec<u8>>,
+    candidates: Vec<(Region, usize)>,
+}
+
+impl Search {
+    pub fn new(memory: &Memory) -> Self {
+        let mut search = Self {
+            word: false,
+            signed: false,
+            snapshot: Vec::new(),
+            candidates: Vec::new(),
+        };
+        search.reset(memory);
+        search
+    }
+
+    // Compares little endian 16 bit values instead of single bytes.
+    pub fn with_word(mut self, word: bool) -> Self {
+        self.word = word;
+        self
+    }
+
+    pub fn with_signed(mut self, signed: bool) -> Self {
+        self.signed = signed;
+        self
+    }
+
+    pub fn reset(&mut self, memory: &Memory) {
+        self.snapshot = Self::snapshot(memory);
+        self.candidates = REGIONS
+            .iter()
+            .flat_map(|&region| (0..region.data(memory).len()).map(move |i| (region, i)))
+            .collect();
+    }
+
+    pub fn filter(&mut self, memory: &Memory, filter: Filter) {
+        let snapshot = Self::snapshot(memory);
+        let previous = std::mem::replace(&mut self.snapshot, snapshot);
+        let candidates = std::mem::take(&mut self.candidates);
+        self.candidates = candidates
+            .into_iter()
+            .filter(|&(region, offset)| {
+                let old = self.value(&previous, region, offset);
+                let new = self.value(&self.snapshot, region, offset);
+                match (old, new) {
+                    (Some(old), Some(new)) => match filter {
+                        Filter::Equal => new == old,
+                        Filter::Changed => new != old,
+                        Filter::Increased => new > old,
+                        Filter::Decreased => new < old,
+                        Filter::Value(value) => new == value,
+                    },
+                    _ => false,
+                }
+            })
+            .collect();
+    }
+
+    pub fn len(&self) -> usize {
+        self.candidates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.candidates.is_empty()
+    }
+
+    pub fn candidates(&self) -> impl Iterator<Item = Candidate> + '_ {
+        self.candidates.iter().filter_map(move |&(region, offset)| {
+            Some(Candidate {
+                region,
+                offset,
+                value: self.value(&self.snapshot, region, offset)?,
+            })
+        })
+    }
+
+    fn snapshot(memory: &Memory) -> Vec<Vec<u8>> {
+        REGIONS.iter().map(|r| r.data(memory).to_vec()).collect()
+    }
+
+    fn value(&self, snapshot: &[Vec<u8>], region: Region, offset: usize) -> Option<i32> {
+        let data = &snapshot[REGIONS.iter().position(|&r| r == region)?];
+        let low = *data.get(offset)?;
+        Some(match (self.word, self.signed) {
+            (false, false) => low as i32,
+            (false, true) => low as i8 as i32,
+            (true, signed) => {
+                let word = u16::from_le_bytes([low, *data.get(offset + 1)?]);
+                if signed {
+                    word as i16 as i32
+                } else {
+                    word as i32
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn narrows_down_candidates() {
+        let mut memory = Memory::new();
+        memory.write(0xc010, 5);
+        memory.write(0xff90, 5);
+        let mut search = Search::new(&memory);
+
+        memory.write(0xc010, 4);
+        memory.write(0xff90, 6);
+        search.filter(&memory, Filter::Decreased);
+        let found = search.candidates().collect::<Vec<_>>();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].to_string(), "c010: 4");
+
+        let mut search = Search::new(&memory).with_word(true).with_signed(true);
+        memory.write(0xc010, 0xff);
+        memory.write(0xc011, 0xff);
+        search.filter(&memory, Filter::Value(-1));
+        assert_eq!(search.len(), 1);
+        search.filter(&memory, Filter::Equal);
+        assert_eq!(search.len(), 1);
+    }
+}
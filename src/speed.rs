@@ -0,0 +1,135 @@
+// The Game Boy draws a frame every 70224 cycles of its 4 MiHz clock, slightly slower than 60 Hz.
+pub const FRAME_RATE: f64 = 4_194_304.0 / 70_224.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum State {
+    Normal,
+    Paused,
+    FastForward,
+    SlowMotion,
+}
+
+// Works out how many frames to emulate for the real time that passed, keeping the leftover
+// fraction of a frame for the next time.
+pub struct Speed {
+    // None runs as many frames as the host manages.
+    fast_forward: Option<f64>,
+    slow_motion: f64,
+
+    paused: bool,
+    fast: bool,
+    slow: bool,
+    advance: bool,
+    owed: f64,
+}
+
+impl Speed {
+    pub fn new() -> Self {
+        Self {
+            fast_forward: Some(4.0),
+            slow_motion: 0.5,
+
+            paused: false,
+            fast: false,
+            slow: false,
+            advance: false,
+            owed: 0.0,
+        }
+    }
+
+    pub fn with_fast_forward(mut self, fast_forward: Option<f64>) -> Self {
+        self.fast_forward = fast_forward;
+        self
+    }
+
+    pub fn with_slow_motion(mut self, slow_motion: f64) -> Self {
+        self.slow_motion = slow_motion;
+        self
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    // Runs a single frame while paused.
+    pub fn advance(&mut self) {
+        self.paused = true;
+        self.advance = true;
+    }
+
+    pub fn set_fast_forward(&mut self, fast: bool) {
+        self.fast = fast;
+    }
+
+    pub fn set_slow_motion(&mut self, slow: bool) {
+        self.slow = slow;
+    }
+
+    pub fn state(&self) -> State {
+        match (self.paused, self.fast, self.slow) {
+            (true, _, _) => State::Paused,
+            (false, true, _) => State::FastForward,
+            (false, false, true) => State::SlowMotion,
+            _ => State::Normal,
+        }
+    }
+
+    // The number of frames due after dt seconds, or usize::MAX when fast forwarding uncapped.
+    pub fn frames(&mut self, dt: f64) -> usize {
+        let speed = match self.state() {
+            State::Paused => {
+                self.owed = 0.0;
+                return std::mem::take(&mut self.advance) as usize;
+            }
+            State::FastForward => match self.fast_forward {
+                Some(speed) => speed,
+                None => {
+                    self.owed = 0.0;
+                    return usize::MAX;
+                }
+            },
+            State::SlowMotion => self.slow_motion,
+            State::Normal => 1.0,
+        };
+        self.owed += dt * speed * FRAME_RATE;
+        let frames = self.owed as usize;
+        self.owed -= frames as f64;
+        frames
+    }
+}
+
+impl Default for Speed {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn paces_frames() {
+        let mut speed = Speed::new();
+        assert_eq!(speed.frames(1.0), 59);
+        assert_eq!(speed.frames(1.0), 60);
+
+        speed.toggle_pause();
+        assert_eq!(speed.frames(1.0), 0);
+        speed.advance();
+        assert_eq!(speed.frames(1.0), 1);
+        assert_eq!(speed.frames(1.0), 0);
+        speed.toggle_pause();
+
+        speed.set_fast_forward(true);
+        assert_eq!(speed.frames(0.25), 59);
+        speed.set_fast_forward(false);
+        speed.set_slow_motion(true);
+        assert_eq!(speed.state(), State::SlowMotion);
+        assert_eq!(speed.frames(2.0), 60);
+
+        let mut speed = Speed::new().with_fast_forward(None);
+        speed.set_fast_forward(true);
+        assert_eq!(speed.frames(1.0), usize::MAX);
+    }
+}
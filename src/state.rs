@@ -0,0 +1,87 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+use thiserror::Error;
+
+const MAGIC: &[u8] = b"GBST";
+
+// Machine states are everything that changes while running, leaving out the rom itself and the
+// frontend hooks, encoded with bincode.
+pub fn to_bytes<T: Serialize>(value: &T) -> Vec<u8> {
+    bincode::serialize(value).expect("machine state is always serializable")
+}
+
+pub fn from_bytes<T: DeserializeOwned>(data: &[u8]) -> Result<T, Error> {
+    Ok(bincode::deserialize(data)?)
+}
+
+#[derive(Serialize, Deserialize)]
+struct StateFile {
+    rom_crc: u32,
+    state: Vec<u8>,
+}
+
+// Save state files carry the checksum of the rom they were made with, as restoring one into
+// another game would only crash it.
+pub fn write_file(path: &Path, rom_crc: u32, state: Vec<u8>) -> Result<(), Error> {
+    let file = StateFile { rom_crc, state };
+    fs::write(path, [MAGIC, &to_bytes(&file)].concat())?;
+    Ok(())
+}
+
+pub fn read_file(path: &Path, rom_crc: u32) -> Result<Vec<u8>, Error> {
+    let data = fs::read(path)?;
+    if !data.starts_with(MAGIC) {
+        return Err(Error::NotAState);
+    }
+    let file: StateFile = from_bytes(&data[MAGIC.len()..])?;
+    if file.rom_crc != rom_crc {
+        return Err(Error::WrongRom);
+    }
+    Ok(file.state)
+}
+
+// Serde only implements arrays of up to 32 elements, so larger ones go through a byte slice.
+pub mod array {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+    use std::convert::TryInto;
+
+    pub fn serialize<S: Serializer, const N: usize>(
+        array: &[u8; N],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(array)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(
+        deserializer: D,
+    ) -> Result<[u8; N], D::Error> {
+        let bytes = Vec::<u8>::deserialize(deserializer)?;
+        bytes
+            .try_into()
+            .map_err(|_| de::Error::custom("array length mismatch"))
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("not a save state")]
+    NotAState,
+    #[error("save state was made with a different rom")]
+    WrongRom,
+    #[error("save state was made while running the bootrom")]
+    Booting,
+    #[error("save state does not fit this machine")]
+    Mismatch,
+    #[error("save state is corrupt: {source}")]
+    Corrupt {
+        #[from]
+        source: bincode::Error,
+    },
+    #[error("io error: {source}")]
+    Io {
+        #[from]
+        source: io::Error,
+    },
+}
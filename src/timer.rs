@@ -1,5 +1,8 @@
+use serde::{Deserialize, Serialize};
+
 pub type Timing = u16;
 
+#[derive(Serialize, Deserialize)]
 pub struct Timer {
     div: SubTimer,
     tima: SubTimer,
@@ -63,7 +66,7 @@ impl Timer {
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Serialize, Deserialize)]
 struct SubTimer {
     timer: u32,
     value: u8,
@@ -104,7 +107,7 @@ impl From<SubTimer> for u8 {
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Serialize, Deserialize)]
 struct TAC {
     start: bool,
     clock: u8,
@@ -1,67 +1,346 @@
+use crate::config::{self, Action, Config};
 use crate::cpu::CPU;
 use crate::joypad;
 use crate::lcd;
+use crate::rewind::Rewind;
+use crate::speed::{self, Speed};
+use crate::video::{self, Video};
 
 use ::image as im;
+use gilrs::Gilrs;
 use piston_window::*;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
-const SCALE: u32 = 2;
+// Updating more often than the screen refreshes keeps the pacing of frames even.
+const UPDATES_PER_SECOND: u64 = 240;
 const SCREEN_WIDTH: u32 = lcd::SCREEN_SIZE.0 as u32;
 const SCREEN_HEIGHT: u32 = lcd::SCREEN_SIZE.1 as u32;
+const SCREEN: [f64; 2] = [SCREEN_WIDTH as f64, SCREEN_HEIGHT as f64];
+// In screen pixels.
+const SHAKE: [(f64, f64); 4] = [(1.0, 0.0), (-1.0, 0.5), (0.5, -1.0), (-0.5, 1.0)];
 
-pub fn launch(mut cpu: CPU) -> Result<(), Box<dyn std::error::Error>> {
-    let mut window: PistonWindow =
-        WindowSettings::new("GeeBee", (SCREEN_WIDTH * SCALE, SCREEN_HEIGHT * SCALE))
-            .resizable(false)
-            .build()?;
-    window.set_ups(60);
+pub fn launch(
+    cpu: &mut CPU,
+    mut rewind: Rewind,
+    speed: Speed,
+    config: &Config,
+    video: Video,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let keys: HashMap<Key, Action> = config.keyboard()?;
+    let pad = Pad::new(config)?;
+    // Playing without a gamepad is fine, so a missing gamepad driver is only worth a warning.
+    let mut gilrs = match Gilrs::new() {
+        Ok(gilrs) => Some(gilrs),
+        Err(e) => {
+            eprintln!("warning: gamepads unavailable: {}", e);
+            None
+        }
+    };
+
+    let mut window: PistonWindow = WindowSettings::new(
+        "GeeBee",
+        (SCREEN_WIDTH * video.scale, SCREEN_HEIGHT * video.scale),
+    )
+    .fullscreen(video.fullscreen)
+    .build()?;
+    window.set_ups(UPDATES_PER_SECOND);
     let mut texture_context = window.create_texture_context();
     let mut texture = Texture::from_image(
         &mut texture_context,
-        &image_buffer(&cpu),
+        &image_buffer(cpu),
         &TextureSettings::new().filter(texture::Filter::Nearest),
     )
     .unwrap();
+
+    let rumble = Rc::new(Cell::new(false));
+    {
+        let rumble = rumble.clone();
+        cpu.on_rumble(move |on| rumble.set(on));
+    }
+    let mut frame = 0;
+    let mut controls = Controls::new(speed, config.macros()?, video.fullscreen);
+
+    // Dragging with the left mouse button tilts relative to the center of the window. A gamepad
+    // stick tilts while it is pushed, and the tilt keys fully in a direction while held.
+    let mut cursor = [0.0, 0.0];
+    let mut dragging = false;
+    let mut pad_tilt = (0.0, 0.0);
+
     while let Some(e) = window.next() {
+        while let Some(gilrs::Event { event, .. }) = gilrs.as_mut().and_then(Gilrs::next_event) {
+            match event {
+                gilrs::EventType::ButtonPressed(button, _) => {
+                    if let Some(&action) = pad.buttons.get(&button) {
+                        controls.act(cpu, action, true);
+                    }
+                }
+                gilrs::EventType::ButtonReleased(button, _) => {
+                    if let Some(&action) = pad.buttons.get(&button) {
+                        controls.act(cpu, action, false);
+                    }
+                }
+                gilrs::EventType::AxisChanged(axis, value, _) => {
+                    let value = if value.abs() < pad.deadzone {
+                        0.0
+                    } else {
+                        value
+                    };
+                    // Gamepads point their y axes up, while the d-pad and tilt point down.
+                    let axis = Some(axis);
+                    if axis == pad.horizontal {
+                        controls.stick(cpu, 0, value);
+                    }
+                    if axis == pad.vertical {
+                        controls.stick(cpu, 1, -value);
+                    }
+                    if axis == pad.tilt_x {
+                        pad_tilt.0 = value;
+                    }
+                    if axis == pad.tilt_y {
+                        pad_tilt.1 = -value;
+                    }
+                }
+                _ => {}
+            }
+        }
+
         if let Some(Button::Keyboard(key)) = e.press_args() {
-            if let Some(b) = map_button(key) {
-                cpu.joypad().press(b);
+            if let Some(&action) = keys.get(&key) {
+                controls.act(cpu, action, true);
             }
         }
         if let Some(Button::Keyboard(key)) = e.release_args() {
-            if let Some(b) = map_button(key) {
-                cpu.joypad().release(b);
+            if let Some(&action) = keys.get(&key) {
+                controls.act(cpu, action, false);
             }
         }
+        if let Some(Button::Mouse(MouseButton::Left)) = e.press_args() {
+            dragging = true;
+        }
+        if let Some(Button::Mouse(MouseButton::Left)) = e.release_args() {
+            dragging = false;
+        }
+        if let Some(position) = e.mouse_cursor_args() {
+            cursor = position;
+        }
+        if let Some(fullscreen) = controls.toggled_fullscreen.take() {
+            let window = window.window.ctx.window();
+            window.set_fullscreen(Some(window.get_current_monitor()).filter(|_| fullscreen));
+        }
 
-        if e.update_args().is_some() {
-            cpu.cycle();
-            texture
-                .update(&mut texture_context, &image_buffer(&cpu))
-                .unwrap();
+        let (x, y) = if dragging {
+            let size = window.size();
+            let (corner, zoom) = video::viewport(video.scaling, [size.width, size.height], SCREEN);
+            let half = [SCREEN[0] * zoom / 2.0, SCREEN[1] * zoom / 2.0];
+            (
+                ((cursor[0] - corner[0] - half[0]) / half[0]) as f32,
+                ((cursor[1] - corner[1] - half[1]) / half[1]) as f32,
+            )
+        } else if pad_tilt != (0.0, 0.0) {
+            pad_tilt
+        } else {
+            let axis = |negative: bool, positive: bool| match (negative, positive) {
+                (true, false) => -1.0,
+                (false, true) => 1.0,
+                _ => 0.0,
+            };
+            let tilt = controls.tilt;
+            (axis(tilt[2], tilt[3]), axis(tilt[0], tilt[1]))
+        };
+        if !cpu.playing_movie() {
+            cpu.joypad().tilt(x, y);
         }
+
+        if let Some(args) = e.update_args() {
+            // Frames that do not fit before the next update are dropped, so a slow host or an
+            // uncapped fast forward never falls behind the window.
+            let deadline = Instant::now() + Duration::from_secs_f64(args.dt);
+            let frames = controls.speed.frames(args.dt);
+            let mut ran = 0;
+            while ran < frames && (ran == 0 || Instant::now() < deadline) {
+                // Rewinding would desync a movie from the input it records or plays back.
+                if controls.rewinding && cpu.movie().is_none() {
+                    // Step back a snapshot every frame. Loading a state keeps what the player
+                    // holds.
+                    if let Some(state) = rewind.pop() {
+                        cpu.load_state(&state)?;
+                    }
+                } else {
+                    cpu.cycle();
+                    rewind.frame(cpu);
+                }
+                frame += 1;
+                ran += 1;
+            }
+            if ran > 0 {
+                texture
+                    .update(&mut texture_context, &image_buffer(cpu))
+                    .unwrap();
+            }
+        }
+        let state = controls.speed.state();
         window.draw_2d(&e, |c, g, d| {
             texture_context.encoder.flush(d);
-            image(&texture, c.transform.zoom(SCALE as f64), g);
+            // Shake the screen around while the rumble motor is running.
+            let (x, y) = if rumble.get() {
+                SHAKE[frame % SHAKE.len()]
+            } else {
+                (0.0, 0.0)
+            };
+            let (corner, zoom) = video::viewport(video.scaling, c.get_view_size(), SCREEN);
+            clear([0.0, 0.0, 0.0, 1.0], g);
+            let transform = c.transform.trans(corner[0], corner[1]).zoom(zoom);
+            image(&texture, transform.trans(x, y), g);
+            draw_indicator(
+                state,
+                c.transform.trans(corner[0] + SCREEN[0] * zoom, corner[1]),
+                g,
+            );
         });
     }
     Ok(())
 }
 
+// The gamepad side of the config, resolved into gilrs buttons and axes.
+struct Pad {
+    deadzone: f32,
+    buttons: HashMap<gilrs::Button, Action>,
+    horizontal: Option<gilrs::Axis>,
+    vertical: Option<gilrs::Axis>,
+    tilt_x: Option<gilrs::Axis>,
+    tilt_y: Option<gilrs::Axis>,
+}
+
+impl Pad {
+    fn new(config: &Config) -> Result<Self, config::Error> {
+        let axes = &config.gamepad.axes;
+        let axis = |name: &Option<String>| name.as_deref().map(config::resolve).transpose();
+        Ok(Self {
+            deadzone: config.gamepad.deadzone,
+            buttons: config.gamepad_buttons()?,
+            horizontal: axis(&axes.horizontal)?,
+            vertical: axis(&axes.vertical)?,
+            tilt_x: axis(&axes.tilt_x)?,
+            tilt_y: axis(&axes.tilt_y)?,
+        })
+    }
+}
+
+// What the keyboard and gamepads drive besides the joypad.
+struct Controls {
+    speed: Speed,
+    fullscreen: bool,
+    // Set when the fullscreen hotkey was pressed, for the window to catch up with.
+    toggled_fullscreen: Option<bool>,
+    macros: Vec<joypad::Macro>,
+    rewinding: bool,
+    // The direction each stick axis last pushed the d-pad in.
+    sticks: [i32; 2],
+    // Which tilt actions are held, as up, down, left and right.
+    tilt: [bool; 4],
+}
+
+impl Controls {
+    fn new(speed: Speed, macros: Vec<joypad::Macro>, fullscreen: bool) -> Self {
+        Self {
+            speed,
+            fullscreen,
+            toggled_fullscreen: None,
+            macros,
+            rewinding: false,
+            sticks: [0; 2],
+            tilt: [false; 4],
+        }
+    }
+
+    fn act(&mut self, cpu: &mut CPU, action: Action, pressed: bool) {
+        match action {
+            Action::Pause if pressed => self.speed.toggle_pause(),
+            Action::FrameAdvance if pressed => self.speed.advance(),
+            Action::FastForward => self.speed.set_fast_forward(pressed),
+            Action::SlowMotion => self.speed.set_slow_motion(pressed),
+            Action::Rewind => self.rewinding = pressed,
+            Action::Fullscreen if pressed => {
+                self.fullscreen = !self.fullscreen;
+                self.toggled_fullscreen = Some(self.fullscreen);
+            }
+            Action::TiltUp => self.tilt[0] = pressed,
+            Action::TiltDown => self.tilt[1] = pressed,
+            Action::TiltLeft => self.tilt[2] = pressed,
+            Action::TiltRight => self.tilt[3] = pressed,
+            Action::Cheat(i) if pressed => {
+                if let Some(enabled) = cpu.cheats().toggle(i) {
+                    let name = &cpu.cheats().get(i).unwrap().name;
+                    println!("cheat {} {}", name, if enabled { "on" } else { "off" });
+                }
+            }
+            // A movie that is playing back drives the joypad itself.
+            _ if cpu.playing_movie() => {}
+            Action::Macro(i) if pressed => cpu.joypad().play_macro(&self.macros[i]),
+            _ => {
+                let joypad = cpu.joypad();
+                match (action.button(), action.turbo(), pressed) {
+                    (Some(button), _, true) => joypad.press(button),
+                    (Some(button), _, false) => joypad.release(button),
+                    (_, Some(button), true) => joypad.press_turbo(button),
+                    (_, Some(button), false) => joypad.release_turbo(button),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    // Holds the d-pad towards where a stick axis points, with 0 for horizontal and 1 for
+    // vertical.
+    fn stick(&mut self, cpu: &mut CPU, axis: usize, value: f32) {
+        let direction = if value < 0.0 {
+            -1
+        } else if value > 0.0 {
+            1
+        } else {
+            0
+        };
+        if direction == self.sticks[axis] {
+            return;
+        }
+        let (negative, positive) =
+            [(Action::Left, Action::Right), (Action::Up, Action::Down)][axis];
+        for &(direction, pressed) in &[(self.sticks[axis], false), (direction, true)] {
+            match direction {
+                -1 => self.act(cpu, negative, pressed),
+                1 => self.act(cpu, positive, pressed),
+                _ => {}
+            }
+        }
+        self.sticks[axis] = direction;
+    }
+}
+
 fn image_buffer(cpu: &CPU) -> im::RgbaImage {
     im::RgbaImage::from_vec(SCREEN_WIDTH, SCREEN_HEIGHT, cpu.lcd().screen().to_vec()).unwrap()
 }
 
-fn map_button(key: keyboard::Key) -> Option<joypad::Button> {
-    match key {
-        Key::W => Some(joypad::Button::Up),
-        Key::A => Some(joypad::Button::Left),
-        Key::S => Some(joypad::Button::Down),
-        Key::D => Some(joypad::Button::Right),
-        Key::Z => Some(joypad::Button::Start),
-        Key::X => Some(joypad::Button::Select),
-        Key::N => Some(joypad::Button::B),
-        Key::M => Some(joypad::Button::A),
-        _ => None,
+// Draws a symbol left of and below the transform while not running at normal speed.
+fn draw_indicator(state: speed::State, transform: math::Matrix2d, g: &mut G2d) {
+    let color = [1.0, 1.0, 1.0, 0.8];
+    let (x, y) = (-24.0, 8.0);
+    let triangle = |x: f64| [[x, y], [x + 8.0, y + 8.0], [x, y + 16.0]];
+    match state {
+        speed::State::Paused => {
+            rectangle(color, [x, y, 5.0, 16.0], transform, g);
+            rectangle(color, [x + 10.0, y, 5.0, 16.0], transform, g);
+        }
+        speed::State::FastForward => {
+            polygon(color, &triangle(x), transform, g);
+            polygon(color, &triangle(x + 8.0), transform, g);
+        }
+        speed::State::SlowMotion => {
+            rectangle(color, [x, y, 4.0, 16.0], transform, g);
+            polygon(color, &triangle(x + 7.0), transform, g);
+        }
+        speed::State::Normal => {}
     }
 }
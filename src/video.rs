@@ -0,0 +1,66 @@
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scaling {
+    // Whole multiples of the screen size keep every pixel the same size.
+    Integer,
+    Fit,
+}
+
+impl FromStr for Scaling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "integer" => Ok(Scaling::Integer),
+            "fit" => Ok(Scaling::Fit),
+            _ => Err(format!("unknown scaling {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Video {
+    // The initial window size, in multiples of the screen size.
+    pub scale: u32,
+    pub scaling: Scaling,
+    pub fullscreen: bool,
+}
+
+// Where the screen goes in a window of the given size, as its top left corner and zoom. The
+// screen keeps its aspect ratio, with black bars filling the rest of the window.
+pub fn viewport(scaling: Scaling, window: [f64; 2], screen: [f64; 2]) -> ([f64; 2], f64) {
+    let fit = (window[0] / screen[0]).min(window[1] / screen[1]);
+    let zoom = match scaling {
+        // Windows smaller than the screen still show all of it.
+        Scaling::Integer if fit >= 1.0 => fit.floor(),
+        _ => fit,
+    };
+    let corner = [
+        ((window[0] - screen[0] * zoom) / 2.0).floor(),
+        ((window[1] - screen[1] * zoom) / 2.0).floor(),
+    ];
+    (corner, zoom)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn letterboxes_the_screen() {
+        let screen = [160.0, 144.0];
+        assert_eq!(
+            viewport(Scaling::Integer, [800.0, 600.0], screen),
+            ([80.0, 12.0], 4.0)
+        );
+        assert_eq!(
+            viewport(Scaling::Fit, [800.0, 576.0], screen),
+            ([80.0, 0.0], 4.0)
+        );
+        assert_eq!(
+            viewport(Scaling::Integer, [80.0, 144.0], screen),
+            ([0.0, 36.0], 0.5)
+        );
+    }
+}